@@ -1,21 +1,29 @@
-use std::collections::HashMap;
+use indexmap::IndexMap;
 use std::fmt;
 use std::fmt::Debug;
-use crate::ast::pattern_action_fun::VariableElement;
-use crate::ast::shire_expression::{Statement, StatementType};
+use crate::ast::pattern_action_fun::{PatternActionFunc, VariableElement};
+use crate::ast::regex_pattern::RegexPattern;
+use crate::ast::shire_expression::{ConditionCase, Statement, StatementType};
+use crate::runtime::{Environment, Value};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum FrontMatterType {
     STRING(String),
-    NUMBER(i32),
+    NUMBER(i64),
+    /// The number with fraction or exponent, for example: `0.75` or `1e-3`
+    FLOAT(f64),
     DATE(String),
     BOOLEAN(bool),
     ERROR(String),
     EMPTY,
     ARRAY(Vec<FrontMatterType>),
-    OBJECT(HashMap<String, FrontMatterType>),
+    /// The object in declaration order of its keys
+    OBJECT(IndexMap<String, FrontMatterType>),
     PATTERN(RuleBasedPatternAction),
-    CASE_MATCH(HashMap<String, FrontMatterType>),
+    /// The regex literal, for example: `/.*\.java/i`
+    REGEX(RegexPattern),
+    CASE_MATCH(ConditionCase),
     VARIABLE(String),
     EXPRESSION(StatementType),
     IDENTIFIER(String),
@@ -26,40 +34,28 @@ impl FrontMatterType {
     // display 方法实现
     pub fn display(&self) -> String {
         match self {
-            FrontMatterType::STRING(value) => format!("\"{}\"", value),
+            FrontMatterType::STRING(value) => quote_string(value),
             FrontMatterType::NUMBER(value) => value.to_string(),
+            // debug format keeps the fraction of `1.0`, so it's parsed back as float
+            FrontMatterType::FLOAT(value) => format!("{:?}", value),
             FrontMatterType::DATE(value) => value.to_string(),
             FrontMatterType::BOOLEAN(value) => value.to_string(),
             FrontMatterType::ERROR(value) => value.to_string(),
-            FrontMatterType::EMPTY => "".to_string(),
+            // the `null` literal of expression, the empty front matter value is formatted as nothing
+            FrontMatterType::EMPTY => "null".to_string(),
             FrontMatterType::ARRAY(value) => {
                 let elements: Vec<String> = value.iter().map(|e| e.display()).collect();
                 format!("[{}]", elements.join(", "))
             }
             FrontMatterType::OBJECT(value) => {
                 let elements: Vec<String> = value.iter()
-                    .map(|(k, v)| format!("\"{}\": {}", k, v.display()))
+                    .map(|(k, v)| format!("{}: {}", quote_string(k), v.display()))
                     .collect();
                 format!("{{{}}}", elements.join(", "))
             }
             FrontMatterType::PATTERN(value) => format!("{} -> {}", value.pattern, value.processors.iter().map(|p| p.func_name.clone()).collect::<Vec<_>>().join(", ")),
-            FrontMatterType::CASE_MATCH(value) => {
-                let elements: Vec<String> = value.iter().map(|(k, v)| {
-                    let pattern = if let FrontMatterType::PATTERN(pattern) = v {
-                        pattern.pattern.clone()
-                    } else {
-                        "".to_string()
-                    };
-                    let processors = if let FrontMatterType::PATTERN(pattern) = v {
-                        pattern.processors.iter().map(|p| p.func_name.clone()).collect::<Vec<_>>().join(" | ")
-                    } else {
-                        "".to_string()
-                    };
-
-                    format!("\"{}\" {{ {} }}", k, processors)
-                }).collect();
-                format!("case \"$0\" {{\n{}\n}}", elements.join("\n"))
-            }
+            FrontMatterType::REGEX(value) => value.to_string(),
+            FrontMatterType::CASE_MATCH(value) => value.display(),
             FrontMatterType::VARIABLE(value) => format!("${}", value),
             FrontMatterType::EXPRESSION(statement) => statement.display(),
             FrontMatterType::IDENTIFIER(value) => value.to_string(),
@@ -67,54 +63,151 @@ impl FrontMatterType {
         }
     }
 
-    // to_value 方法实现
-    pub fn to_value(&self) -> &dyn std::any::Any {
-        match self {
-            FrontMatterType::STRING(value) => value,
-            FrontMatterType::NUMBER(value) => value,
-            FrontMatterType::DATE(value) => value,
-            FrontMatterType::BOOLEAN(value) => value,
-            FrontMatterType::ERROR(value) => value,
-            FrontMatterType::EMPTY => &"",
-            FrontMatterType::ARRAY(value) => value,
-            FrontMatterType::OBJECT(value) => value,
-            FrontMatterType::PATTERN(value) => value,
-            FrontMatterType::CASE_MATCH(value) => value,
-            FrontMatterType::VARIABLE(value) => value,
-            FrontMatterType::EXPRESSION(statement) => statement,
-            FrontMatterType::IDENTIFIER(value) => value,
-            FrontMatterType::QUERY_STATEMENT(query_statement) => query_statement,
+    /// The runtime value of literal, for example: `[1, "a"]` is the list of int and string, the pipeline is
+    /// kept to run later, and the variable or expression needs to be evaluated with [Statement::evaluate].
+    pub fn to_value(&self) -> Result<Value, String> {
+        let value = match self {
+            FrontMatterType::STRING(value) => Value::String(value.clone()),
+            FrontMatterType::NUMBER(value) => Value::Int(*value),
+            FrontMatterType::FLOAT(value) => Value::Float(*value),
+            FrontMatterType::DATE(value) => Value::Date(value.clone()),
+            FrontMatterType::BOOLEAN(value) => Value::Bool(*value),
+            FrontMatterType::IDENTIFIER(value) => Value::String(value.clone()),
+            FrontMatterType::EMPTY => Value::Null,
+            FrontMatterType::ARRAY(values) => Value::List(values.iter().map(|it| it.to_value()).collect::<Result<_, _>>()?),
+            FrontMatterType::OBJECT(entries) => Value::Object(
+                entries.iter().map(|(key, value)| Ok((key.clone(), value.to_value()?))).collect::<Result<_, String>>()?,
+            ),
+            FrontMatterType::EXPRESSION(StatementType::Processor(processor)) => {
+                Value::Pipeline(processor.processors().to_vec())
+            }
+            FrontMatterType::QUERY_STATEMENT(query) => Value::Pipeline(query.to_pattern_action_funcs()),
+            FrontMatterType::ERROR(message) => return Err(message.clone()),
+            value => return Err(format!("`{}` is not a literal value", value.display())),
+        };
+        Ok(value)
+    }
+
+    /// The literal of runtime value, the inverse of [FrontMatterType::to_value], the file and symbol handles
+    /// are their paths and names.
+    pub fn from_value(value: &Value) -> FrontMatterType {
+        match value {
+            Value::Null => FrontMatterType::EMPTY,
+            Value::Bool(value) => FrontMatterType::BOOLEAN(*value),
+            Value::Int(value) => FrontMatterType::NUMBER(*value),
+            Value::Float(value) => FrontMatterType::FLOAT(*value),
+            Value::String(value) | Value::File(value) | Value::Symbol(value) => FrontMatterType::STRING(value.clone()),
+            Value::Date(value) => FrontMatterType::DATE(value.clone()),
+            Value::List(values) => FrontMatterType::ARRAY(values.iter().map(FrontMatterType::from_value).collect()),
+            Value::Object(entries) => FrontMatterType::OBJECT(
+                entries.iter().map(|(key, value)| (key.clone(), FrontMatterType::from_value(value))).collect(),
+            ),
+            Value::Pipeline(processors) => FrontMatterType::EXPRESSION(StatementType::Processor(
+                crate::ast::shire_expression::Processor::new(processors.clone()),
+            )),
+        }
+    }
+}
+
+/// Quote the string as a literal in Shire, the special chars are escaped so that it can be parsed back.
+pub(crate) fn quote_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\0' => quoted.push_str("\\0"),
+            c => quoted.push(c),
         }
     }
+    quoted.push('"');
+    quoted
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RuleBasedPatternAction {
     pattern: String,
     processors: Vec<Processor>,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Processor {
     func_name: String,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// The PSI query for code elements, for example:
+/// ```shire
+/// from {
+///     PsiClass clazz
+/// }
+/// where {
+///     clazz.getName() == "Foo"
+/// }
+/// select {
+///     clazz.id, clazz.name
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ShirePsiQueryStatement {
-    from: Vec<VariableElement>,
-    where_clause: Box<StatementType>,
-    select: Vec<StatementType>,
+    pub from: Vec<VariableElement>,
+    pub where_clause: Box<StatementType>,
+    pub select: Vec<StatementType>,
+}
+
+impl ShirePsiQueryStatement {
+    pub fn new(from: Vec<VariableElement>, where_clause: StatementType, select: Vec<StatementType>) -> Self {
+        ShirePsiQueryStatement { from, where_clause: Box::new(where_clause), select }
+    }
+
+    /// The query as the `from`, `where` and `select` functions of pipeline
+    pub fn to_pattern_action_funcs(&self) -> Vec<PatternActionFunc> {
+        vec![
+            PatternActionFunc::From { variables: self.from.clone() },
+            PatternActionFunc::Where { statement: self.where_clause.as_ref().clone() },
+            PatternActionFunc::Select { statements: self.select.clone() },
+        ]
+    }
+
+    /// The scope where the `from` variables are bound to `elements` in order, for example: `clazz` in
+    /// `from { PsiClass clazz }` is bound to the first element.
+    pub fn bind<'a>(&self, env: &'a Environment<'a>, elements: &[Value]) -> Result<Environment<'a>, String> {
+        if elements.len() != self.from.len() {
+            return Err(format!("query binds {} variables, but got {} elements", self.from.len(), elements.len()));
+        }
+
+        let mut scope = env.child();
+        for (variable, element) in self.from.iter().zip(elements) {
+            scope.define(variable.name.clone(), element.clone());
+        }
+        Ok(scope)
+    }
+
+    /// Whether the bound elements satisfy the `where` clause
+    pub fn matches(&self, env: &Environment, elements: &[Value]) -> Result<bool, String> {
+        self.where_clause.evaluate(&self.bind(env, elements)?)?.expect_bool("where clause")
+    }
+
+    /// The values of `select` for the bound elements
+    pub fn select(&self, env: &Environment, elements: &[Value]) -> Result<Vec<Value>, String> {
+        let scope = self.bind(env, elements)?;
+        self.select.iter().map(|statement| statement.evaluate(&scope)).collect()
+    }
 }
 
 impl fmt::Display for ShirePsiQueryStatement {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let from_str = self.from.iter().map(|_| "VariableElement").collect::<Vec<&str>>().join(", ");
-        let select_str = self.select.iter().map(|_| "Statement").collect::<Vec<&str>>().join(", ");
+        let from_str = self.from.iter().map(|it| it.to_string()).collect::<Vec<String>>().join(", ");
+        let select_str = self.select.iter().map(|it| it.display()).collect::<Vec<String>>().join(", ");
 
         write!(
             f,
-            "from {{\n    {}\n}}\nwhere {{\n    Statement\n}}\nselect {}",
+            "from {{\n    {}\n}}\nwhere {{\n    {}\n}}\nselect {{\n    {}\n}}",
             from_str,
+            self.where_clause.display(),
             select_str
         )
     }
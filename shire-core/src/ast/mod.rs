@@ -1,3 +1,4 @@
 pub mod pattern_action_fun;
 pub mod shire_expression;
-pub mod front_matter_type;
\ No newline at end of file
+pub mod front_matter_type;
+pub mod regex_pattern;
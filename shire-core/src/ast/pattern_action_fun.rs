@@ -1,26 +1,29 @@
-use crate::ast::shire_expression::StatementType;
+use crate::ast::front_matter_type::FrontMatterType;
+use crate::ast::shire_expression::{evaluate_operand, ConditionCase, Processor, Statement, StatementType};
+use crate::runtime::Environment;
+use serde::{Deserialize, Serialize};
 
 // 使用derive宏自动生成调试信息
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum PatternActionFunc {
     /// Prompt variant for displaying a message prompt.
-    Prompt { message: String },
+    Prompt { message: FrontMatterType },
 
     /// Grep variant for searching with one or more patterns.
-    Grep { patterns: Vec<String> },
+    Grep { patterns: Vec<FrontMatterType> },
 
     /// Sed variant for find and replace operations.
     Sed {
-        pattern: String,
-        replacements: String,
+        pattern: FrontMatterType,
+        replacements: FrontMatterType,
         is_regex: bool,
     },
 
     /// Sort variant for sorting with one or more arguments.
-    Sort { arguments: Vec<String> },
+    Sort { arguments: Vec<FrontMatterType> },
 
     /// Uniq variant for removing duplicates based on one or more arguments.
-    Uniq { texts: Vec<String> },
+    Uniq { texts: Vec<FrontMatterType> },
 
     /// Head variant for retrieving the first few lines.
     Head { number: usize },
@@ -29,13 +32,13 @@ pub enum PatternActionFunc {
     Tail { number: usize },
 
     /// Xargs variant for processing one or more variables.
-    Xargs { variables: Vec<String> },
+    Xargs { variables: Vec<FrontMatterType> },
 
     /// Print variant for printing one or more texts.
-    Print { texts: Vec<String> },
+    Print { texts: Vec<FrontMatterType> },
 
     /// Cat variant for concatenating one or more files.
-    Cat { paths: Vec<String> },
+    Cat { paths: Vec<FrontMatterType> },
 
     /// From variant for selecting one or more elements.
     From { variables: Vec<VariableElement> },
@@ -48,78 +51,535 @@ pub enum PatternActionFunc {
 
     /// Execute a shire script
     ExecuteShire {
-        filename: String,
-        variable_names: Vec<String>,
+        filename: FrontMatterType,
+        variable_names: Vec<FrontMatterType>,
     },
 
     /// Use IDE Notify
-    Notify { message: String },
+    Notify { message: FrontMatterType },
 
     /// Case Match
-    CaseMatch { key_value: Vec<CaseKeyValue> },
+    CaseMatch { case: ConditionCase },
 
     /// Splitting
-    Splitting { paths: Vec<String> },
+    Splitting { paths: Vec<FrontMatterType> },
 
     /// Embedding text
-    Embedding { entries: Vec<String> },
+    Embedding { entries: Vec<FrontMatterType> },
 
     /// Searching text
     Searching {
-        text: String,
-        threshold: u64,
+        text: FrontMatterType,
+        threshold: f64,
     },
 
     /// Caching semantic
-    Caching { text: String },
+    Caching { text: FrontMatterType },
 
     /// Reranking the result
-    Reranking { r#type: String },
+    Reranking { r#type: FrontMatterType },
 
     /// The Redact variant for handling sensitive data by applying a specified redaction strategy.
-    Redact { strategy: String },
+    Redact { strategy: FrontMatterType },
 
     /// The Crawl variant is used to crawl a list of URLs, get markdown from HTML and save it to a file.
-    Crawl { urls: Vec<String> },
+    Crawl { urls: Vec<FrontMatterType> },
 
     /// The Capture variant used to capture file by NodeType
     Capture {
-        file_name: String,
-        node_type: String,
+        file_name: FrontMatterType,
+        node_type: FrontMatterType,
     },
 
     /// The Thread variant will run the function in a new thread
     Thread {
-        file_name: String,
-        variable_names: Vec<String>,
+        file_name: FrontMatterType,
+        variable_names: Vec<FrontMatterType>,
     },
 
     /// The JsonPath variant will parse the JSON and get the value by JSONPath
     JsonPath {
-        obj: Option<String>,
-        path: String,
+        obj: Option<FrontMatterType>,
+        path: FrontMatterType,
     },
 
-    /// User Custom Functions
+    /// User Custom Functions, the arguments are kept as they are written, for example: `foo($a, n=5)`
     ToolchainFunction {
         func_name: String,
-        args: Vec<String>,
+        args: Vec<FunctionArg>,
     },
 }
 
+impl PatternActionFunc {
+    /// Create the function by the name used in pipeline from text arguments, for example: `grep("error")` or
+    /// `head("10")`, see [PatternActionFunc::from_args].
+    pub fn from_name(func_name: &str, args: Vec<String>) -> PatternActionFunc {
+        PatternActionFunc::from_args(func_name, string_args(args))
+    }
+
+    /// Create the function like [PatternActionFunc::from_name], but check the arguments first,
+    /// returns the message of misuse, for example: `head("ten")` or `sed("a")`.
+    pub fn try_from_name(func_name: &str, args: Vec<String>) -> Result<PatternActionFunc, String> {
+        PatternActionFunc::try_from_args(func_name, string_args(args))
+    }
+
+    /// Create the function from the typed arguments, for example: `grep("error")`, `head(n=5)` or `sed($p, "x")`,
+    /// the named arguments are moved to the position of their parameters, and unknown functions are treated
+    /// as user custom toolchain functions.
+    ///
+    /// The variables and expressions are kept in the arguments, they are known only when the pipeline runs.
+    /// The call whose arguments can't be lowered yet, like `head("ten")`, `head($n)` or `searching("q", NaN)`,
+    /// is kept as it's written in [PatternActionFunc::ToolchainFunction], [PatternActionFunc::try_from_args]
+    /// reports the misuse and [PatternActionFunc::resolve] lowers it with the values of variables.
+    pub fn from_args(func_name: &str, args: Vec<FunctionArg>) -> PatternActionFunc {
+        let lowered = parameters(func_name)
+            .and_then(|parameters| resolve_args(func_name, parameters, &args).ok())
+            .and_then(|values| lower(func_name, values));
+        lowered.unwrap_or_else(|| PatternActionFunc::ToolchainFunction { func_name: func_name.to_string(), args })
+    }
+
+    /// Create the function like [PatternActionFunc::from_args], but check the arguments first,
+    /// returns the message of misuse, for example: `head(lines=5)`, `head(n=5, n=6)` or `head("ten")`.
+    pub fn try_from_args(func_name: &str, args: Vec<FunctionArg>) -> Result<PatternActionFunc, String> {
+        if let Some(parameters) = parameters(func_name) {
+            check_args(func_name, &resolve_args(func_name, parameters, &args)?)?;
+        }
+
+        Ok(PatternActionFunc::from_args(func_name, args))
+    }
+
+    /// Evaluate the variables and expressions in arguments with `env` when the pipeline runs, then lower and
+    /// check the call again, for example: `head($n)` is `head(5)` if `$n` is `5`, and an error if it's `"ten"`.
+    pub fn resolve(&self, env: &Environment) -> Result<PatternActionFunc, String> {
+        let Some((func_name, args)) = self.call() else {
+            return Ok(self.clone());
+        };
+
+        let args = args
+            .into_iter()
+            .map(|arg| Ok(FunctionArg { name: arg.name, value: resolve_value(arg.value, env)? }))
+            .collect::<Result<Vec<_>, String>>()?;
+        PatternActionFunc::try_from_args(&func_name, args)
+    }
+
+    /// The name and positional arguments of function as it's called in pipeline, `None` for the clauses of
+    /// query and the case block.
+    fn call(&self) -> Option<(String, Vec<FunctionArg>)> {
+        let (name, values): (&str, Vec<FrontMatterType>) = match self {
+            PatternActionFunc::Prompt { message } => ("prompt", vec![message.clone()]),
+            PatternActionFunc::Grep { patterns } => ("grep", patterns.clone()),
+            PatternActionFunc::Sed { pattern, replacements, is_regex } => {
+                let mut values = vec![pattern.clone(), replacements.clone()];
+                if !is_regex {
+                    values.push(FrontMatterType::STRING("false".to_string()));
+                }
+                ("sed", values)
+            }
+            PatternActionFunc::Sort { arguments } => ("sort", arguments.clone()),
+            PatternActionFunc::Uniq { texts } => ("uniq", texts.clone()),
+            PatternActionFunc::Head { number } => ("head", vec![FrontMatterType::NUMBER(*number as i64)]),
+            PatternActionFunc::Tail { number } => ("tail", vec![FrontMatterType::NUMBER(*number as i64)]),
+            PatternActionFunc::Xargs { variables } => ("xargs", variables.clone()),
+            PatternActionFunc::Print { texts } => ("print", texts.clone()),
+            PatternActionFunc::Cat { paths } => ("cat", paths.clone()),
+            PatternActionFunc::ExecuteShire { filename, variable_names } => {
+                ("execute", [filename].into_iter().chain(variable_names).cloned().collect())
+            }
+            PatternActionFunc::Notify { message } => ("notify", vec![message.clone()]),
+            PatternActionFunc::Splitting { paths } => ("splitting", paths.clone()),
+            PatternActionFunc::Embedding { entries } => ("embedding", entries.clone()),
+            PatternActionFunc::Searching { text, threshold } => {
+                ("searching", vec![text.clone(), FrontMatterType::FLOAT(*threshold)])
+            }
+            PatternActionFunc::Caching { text } => ("caching", vec![text.clone()]),
+            PatternActionFunc::Reranking { r#type } => ("reranking", vec![r#type.clone()]),
+            PatternActionFunc::Redact { strategy } => ("redact", vec![strategy.clone()]),
+            PatternActionFunc::Crawl { urls } => ("crawl", urls.clone()),
+            PatternActionFunc::Capture { file_name, node_type } => {
+                ("capture", vec![file_name.clone(), node_type.clone()])
+            }
+            PatternActionFunc::Thread { file_name, variable_names } => {
+                ("thread", [file_name].into_iter().chain(variable_names).cloned().collect())
+            }
+            PatternActionFunc::JsonPath { obj, path } => ("jsonpath", obj.iter().chain([path]).cloned().collect()),
+            PatternActionFunc::ToolchainFunction { func_name, args } => return Some((func_name.clone(), args.clone())),
+            PatternActionFunc::From { .. }
+            | PatternActionFunc::Where { .. }
+            | PatternActionFunc::Select { .. }
+            | PatternActionFunc::CaseMatch { .. } => return None,
+        };
+
+        Some((name.to_string(), values.into_iter().map(FunctionArg::positional).collect()))
+    }
+}
+
+fn string_args(args: Vec<String>) -> Vec<FunctionArg> {
+    args.into_iter().map(|it| FunctionArg::positional(FrontMatterType::STRING(it))).collect()
+}
+
+/// The argument is known only when the pipeline runs, for example: `$limit` or `$name.length()`
+fn is_deferred(value: &FrontMatterType) -> bool {
+    matches!(value, FrontMatterType::VARIABLE(_) | FrontMatterType::EXPRESSION(_))
+}
+
+/// The text of literal argument, for example: `ten` of `"ten"` or `0.75`, `None` for the other values
+fn literal(value: &FrontMatterType) -> Option<String> {
+    match value {
+        FrontMatterType::STRING(text) => Some(text.clone()),
+        FrontMatterType::NUMBER(_) | FrontMatterType::FLOAT(_) | FrontMatterType::BOOLEAN(_) => Some(value.display()),
+        _ => None,
+    }
+}
+
+/// Evaluate the deferred argument with `env`, the nested call like `trim($name)` is resolved as a pipeline
+fn resolve_value(value: FrontMatterType, env: &Environment) -> Result<FrontMatterType, String> {
+    match value {
+        FrontMatterType::EXPRESSION(StatementType::Processor(processor)) => {
+            let processors = processor.processors().iter().map(|it| it.resolve(env)).collect::<Result<_, _>>()?;
+            Ok(FrontMatterType::EXPRESSION(StatementType::Processor(Processor::new(processors))))
+        }
+        value if is_deferred(&value) => Ok(FrontMatterType::from_value(&evaluate_operand(&value, env)?)),
+        value => Ok(value),
+    }
+}
+
+/// Lower the function with the arguments in positions of its parameters, `None` if the number or flag
+/// argument is not a literal of it.
+fn lower(func_name: &str, values: Vec<FrontMatterType>) -> Option<PatternActionFunc> {
+    let first = values.first().cloned().unwrap_or_else(|| FrontMatterType::STRING(String::new()));
+    let second = || values.get(1).cloned().unwrap_or_else(|| FrontMatterType::STRING(String::new()));
+    let number = |index: usize, default| match values.get(index) {
+        Some(value) => literal(value)?.parse::<usize>().ok(),
+        None => Some(default),
+    };
+
+    let function = match func_name {
+        "prompt" => PatternActionFunc::Prompt { message: first },
+        "grep" => PatternActionFunc::Grep { patterns: values },
+        "sed" => PatternActionFunc::Sed {
+            pattern: first,
+            replacements: second(),
+            is_regex: match values.get(2) {
+                Some(flag) => literal(flag)? != "false",
+                None => true,
+            },
+        },
+        "sort" => PatternActionFunc::Sort { arguments: values },
+        "uniq" => PatternActionFunc::Uniq { texts: values },
+        "head" => PatternActionFunc::Head { number: number(0, 10)? },
+        "tail" => PatternActionFunc::Tail { number: number(0, 10)? },
+        "xargs" => PatternActionFunc::Xargs { variables: values },
+        "print" => PatternActionFunc::Print { texts: values },
+        "cat" => PatternActionFunc::Cat { paths: values },
+        "execute" => PatternActionFunc::ExecuteShire {
+            filename: first,
+            variable_names: values.into_iter().skip(1).collect(),
+        },
+        "notify" => PatternActionFunc::Notify { message: first },
+        "splitting" => PatternActionFunc::Splitting { paths: values },
+        "embedding" => PatternActionFunc::Embedding { entries: values },
+        "searching" => PatternActionFunc::Searching {
+            text: first,
+            threshold: match values.get(1) {
+                Some(value) => literal(value)?.parse::<f64>().ok().filter(|it| it.is_finite())?,
+                None => 0.0,
+            },
+        },
+        "caching" => PatternActionFunc::Caching { text: first },
+        "reranking" => PatternActionFunc::Reranking { r#type: first },
+        "redact" => PatternActionFunc::Redact { strategy: first },
+        "crawl" => PatternActionFunc::Crawl { urls: values },
+        "capture" => PatternActionFunc::Capture { file_name: first, node_type: second() },
+        "thread" => PatternActionFunc::Thread {
+            file_name: first,
+            variable_names: values.into_iter().skip(1).collect(),
+        },
+        "jsonpath" => match values.len() {
+            0 | 1 => PatternActionFunc::JsonPath { obj: None, path: first },
+            _ => PatternActionFunc::JsonPath { obj: Some(first), path: second() },
+        },
+        _ => return None,
+    };
+
+    Some(function)
+}
+
+/// Check the arguments in positions of parameters of builtin function, the deferred arguments are checked
+/// by [PatternActionFunc::resolve] when the pipeline runs.
+fn check_args(func_name: &str, values: &[FrontMatterType]) -> Result<(), String> {
+    let arity = |min: usize, max: Option<usize>| {
+        let found = values.len();
+        let plural = |count: usize| if count == 1 { "argument" } else { "arguments" };
+        match max {
+            Some(max) if min == max && found != min => {
+                Err(format!("`{}` expects {} {}, found {}", func_name, min, plural(min), found))
+            }
+            Some(max) if found > max => {
+                Err(format!("`{}` expects at most {} {}, found {}", func_name, max, plural(max), found))
+            }
+            _ if found < min => {
+                Err(format!("`{}` expects at least {} {}, found {}", func_name, min, plural(min), found))
+            }
+            _ => Ok(()),
+        }
+    };
+    let number = |index: usize, name: &str, is_valid: fn(&str) -> bool| match values.get(index) {
+        Some(value) if !is_deferred(value) && !literal(value).is_some_and(|it| is_valid(&it)) => {
+            let text = literal(value).unwrap_or_else(|| value.display());
+            Err(format!("`{}` expects a number of {}, found `{}`", func_name, name, text))
+        }
+        _ => Ok(()),
+    };
+
+    match func_name {
+        "prompt" | "notify" | "caching" | "reranking" | "redact" => arity(1, Some(1)),
+        "grep" | "cat" | "crawl" | "splitting" | "embedding" | "execute" | "thread" => arity(1, None),
+        "head" | "tail" => arity(0, Some(1)).and_then(|_| number(0, "lines", |it| it.parse::<usize>().is_ok())),
+        "searching" => arity(1, Some(2))
+            .and_then(|_| number(1, "threshold", |it| it.parse::<f64>().is_ok_and(|it| it.is_finite()))),
+        "capture" => arity(2, Some(2)),
+        "jsonpath" => arity(1, Some(2)),
+        "sed" => arity(2, Some(3)).and_then(|_| {
+            let flag = values.get(2).filter(|it| !is_deferred(it));
+            match flag.map(|it| literal(it).unwrap_or_else(|| it.display())).as_deref() {
+                Some("true") | None => match &values[0] {
+                    pattern if is_deferred(pattern) => Ok(()),
+                    pattern => {
+                        let pattern = literal(pattern).unwrap_or_else(|| pattern.display());
+                        regex::Regex::new(&pattern)
+                            .map(|_| ())
+                            .map_err(|_| format!("invalid regex `{}` in `sed`", pattern))
+                    }
+                },
+                Some("false") => Ok(()),
+                Some(flag) => Err(format!("`sed` expects `true` or `false` as regex flag, found `{}`", flag)),
+            }
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// The parameter names of builtin function for named arguments, for example: `head(n=5)`,
+/// returns `None` for the toolchain functions which keep the named arguments as they are written.
+fn parameters(func_name: &str) -> Option<&'static [&'static str]> {
+    match func_name {
+        "prompt" | "notify" => Some(&["message"]),
+        "head" | "tail" => Some(&["n"]),
+        "sed" => Some(&["pattern", "replacement", "regex"]),
+        "searching" => Some(&["text", "threshold"]),
+        "caching" => Some(&["text"]),
+        "reranking" => Some(&["type"]),
+        "redact" => Some(&["strategy"]),
+        "capture" => Some(&["file", "nodeType"]),
+        "execute" | "thread" => Some(&["file"]),
+        "grep" | "sort" | "uniq" | "xargs" | "print" | "cat" | "splitting" | "embedding" | "crawl" | "jsonpath" => {
+            Some(&[])
+        }
+        _ => None,
+    }
+}
+
+/// Resolve the arguments into the positions of `parameters`, named arguments must follow the positional ones.
+fn resolve_args(func_name: &str, parameters: &[&str], args: &[FunctionArg]) -> Result<Vec<FrontMatterType>, String> {
+    let mut resolved: Vec<Option<FrontMatterType>> = vec![];
+    let mut named = false;
+    for arg in args {
+        let Some(name) = &arg.name else {
+            if named {
+                return Err(format!("positional argument follows named argument in `{}`", func_name));
+            }
+            resolved.push(Some(arg.value.clone()));
+            continue;
+        };
+
+        named = true;
+        let index = parameters
+            .iter()
+            .position(|it| it == name)
+            .ok_or_else(|| format!("`{}` has no parameter named `{}`", func_name, name))?;
+        if resolved.len() <= index {
+            resolved.resize(index + 1, None);
+        }
+        if resolved[index].is_some() {
+            return Err(format!("`{}` got multiple values for `{}`", func_name, name));
+        }
+        resolved[index] = Some(arg.value.clone());
+    }
+
+    resolved
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| value.ok_or_else(|| format!("`{}` is missing `{}`", func_name, parameters[index])))
+        .collect()
+}
+
+/// Display the function as it's written in pipeline, for example: `grep("error")`, `head(10)`, `sed($p, "x")`
+/// or `sort`
 impl std::fmt::Display for PatternActionFunc {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            PatternActionFunc::ToolchainFunction { func_name, args } => {
-                write!(f, "{}({})", func_name, args.join(", "))
+            PatternActionFunc::From { variables } => {
+                let variables = variables.iter().map(|it| it.to_string()).collect::<Vec<_>>();
+                write!(f, "from {{ {} }}", variables.join(", "))
+            }
+            PatternActionFunc::Where { statement } => write!(f, "where {{ {} }}", statement.display()),
+            PatternActionFunc::Select { statements } => {
+                let statements = statements.iter().map(|it| it.display()).collect::<Vec<_>>();
+                write!(f, "select {{ {} }}", statements.join(", "))
+            }
+            PatternActionFunc::CaseMatch { case } => write!(f, "{}", case.display()),
+            function => {
+                let (name, args) = function.call().unwrap_or_default();
+                match args.is_empty() {
+                    true => write!(f, "{}", name),
+                    false => {
+                        let args = args.iter().map(|it| it.to_string()).collect::<Vec<_>>();
+                        write!(f, "{}({})", name, args.join(", "))
+                    }
+                }
             }
-            _ => write!(f, "{:?}", self),
         }
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub struct VariableElement; // Replace with actual implementation
+/// The argument of pipeline function, for example: `"error"`, `10`, `$pattern`, `n=5` or `trim($name)`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionArg {
+    pub name: Option<String>,
+    pub value: FrontMatterType,
+}
+
+impl FunctionArg {
+    pub fn positional(value: FrontMatterType) -> Self {
+        FunctionArg { name: None, value }
+    }
+
+    pub fn named(name: String, value: FrontMatterType) -> Self {
+        FunctionArg { name: Some(name), value }
+    }
+}
+
+impl std::fmt::Display for FunctionArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{}={}", name, self.value.display()),
+            None => write!(f, "{}", self.value.display()),
+        }
+    }
+}
+
+/// The element declared in `from` of PSI query, for example: `PsiClass clazz`
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct VariableElement {
+    pub r#type: String,
+    pub name: String,
+}
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub struct CaseKeyValue; // Replace with actual implementation
+impl VariableElement {
+    pub fn new(r#type: String, name: String) -> Self {
+        VariableElement { r#type, name }
+    }
+}
+
+impl std::fmt::Display for VariableElement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.r#type, self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|it| it.to_string()).collect()
+    }
+
+    fn string(value: &str) -> FrontMatterType {
+        FrontMatterType::STRING(value.to_string())
+    }
+
+    #[test]
+    fn test_lower_functions() {
+        assert_eq!(
+            PatternActionFunc::try_from_name("head", args(&["5"])),
+            Ok(PatternActionFunc::Head { number: 5 })
+        );
+        assert_eq!(
+            PatternActionFunc::try_from_name("sed", args(&["a.b", "c", "false"])),
+            Ok(PatternActionFunc::Sed { pattern: string("a.b"), replacements: string("c"), is_regex: false })
+        );
+        assert_eq!(
+            PatternActionFunc::try_from_name("parseCode", args(&["java"])),
+            Ok(PatternActionFunc::ToolchainFunction {
+                func_name: "parseCode".to_string(),
+                args: string_args(args(&["java"])),
+            })
+        );
+    }
+
+    #[test]
+    fn test_report_misused_functions() {
+        let error = |name: &str, arguments: &[&str]| PatternActionFunc::try_from_name(name, args(arguments)).unwrap_err();
+
+        assert_eq!(error("head", &["ten"]), "`head` expects a number of lines, found `ten`");
+        assert_eq!(error("searching", &["q", "NaN"]), "`searching` expects a number of threshold, found `NaN`");
+        assert_eq!(error("grep", &[]), "`grep` expects at least 1 argument, found 0");
+        assert_eq!(error("capture", &["a.java"]), "`capture` expects 2 arguments, found 1");
+        assert_eq!(error("notify", &["a", "b"]), "`notify` expects 1 argument, found 2");
+        assert_eq!(error("jsonpath", &["a", "b", "c"]), "`jsonpath` expects at most 2 arguments, found 3");
+        assert_eq!(error("sed", &["(", "b"]), "invalid regex `(` in `sed`");
+        assert_eq!(error("sed", &["a", "b", "yes"]), "`sed` expects `true` or `false` as regex flag, found `yes`");
+    }
+
+    #[test]
+    fn test_resolve_named_args() {
+        let number = |value: i64| FrontMatterType::NUMBER(value);
+        let named = |name: &str, value: FrontMatterType| FunctionArg::named(name.to_string(), value);
+
+        assert_eq!(
+            PatternActionFunc::try_from_args("head", vec![named("n", number(5))]),
+            Ok(PatternActionFunc::Head { number: 5 })
+        );
+        assert_eq!(
+            PatternActionFunc::try_from_args("sed", vec![
+                FunctionArg::positional(string("a.b")),
+                named("regex", string("false")),
+                named("replacement", string("c")),
+            ]),
+            Ok(PatternActionFunc::Sed { pattern: string("a.b"), replacements: string("c"), is_regex: false })
+        );
+        assert_eq!(
+            PatternActionFunc::try_from_args("parseCode", vec![named("lang", string("java"))]),
+            Ok(PatternActionFunc::ToolchainFunction {
+                func_name: "parseCode".to_string(),
+                args: vec![named("lang", string("java"))],
+            })
+        );
+
+        let error = |name: &str, arguments: Vec<FunctionArg>| PatternActionFunc::try_from_args(name, arguments).unwrap_err();
+        assert_eq!(error("head", vec![named("lines", number(5))]), "`head` has no parameter named `lines`");
+        assert_eq!(error("head", vec![named("n", number(5)), named("n", number(6))]), "`head` got multiple values for `n`");
+        assert_eq!(error("sed", vec![named("replacement", string("c"))]), "`sed` is missing `pattern`");
+        assert_eq!(
+            error("searching", vec![named("text", string("a")), FunctionArg::positional(number(1))]),
+            "positional argument follows named argument in `searching`"
+        );
+    }
+
+    #[test]
+    fn test_display_as_pipeline() {
+        let display = |name: &str, arguments: &[&str]| PatternActionFunc::from_name(name, args(arguments)).to_string();
+
+        assert_eq!(display("grep", &["error", "say \"hi\""]), r#"grep("error", "say \"hi\"")"#);
+        assert_eq!(display("sed", &["a.b", "c", "false"]), r#"sed("a.b", "c", "false")"#);
+        assert_eq!(display("head", &["5"]), "head(5)");
+        assert_eq!(display("searching", &["login", "0.75"]), r#"searching("login", 0.75)"#);
+        assert_eq!(display("jsonpath", &["$.items"]), r#"jsonpath("$.items")"#);
+        assert_eq!(display("sort", &[]), "sort");
+        assert_eq!(display("head", &[]), "head(10)");
+        assert_eq!(display("head", &["ten"]), r#"head("ten")"#);
+        assert_eq!(display("searching", &["q", "NaN"]), r#"searching("q", "NaN")"#);
+        assert_eq!(display("parseCode", &["java"]), r#"parseCode("java")"#);
+    }
+}
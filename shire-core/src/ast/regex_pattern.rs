@@ -0,0 +1,102 @@
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The regex literal in pattern action, for example: `/src\/main\/.*\.java/` or `/error/i`,
+/// the regex is compiled when the literal is parsed.
+///
+/// Supported flags are `i` (case-insensitive), `m` (multi-line), `s` (dot matches new line)
+/// and `x` (ignore whitespace).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "RegexLiteral", try_from = "RegexLiteral")]
+pub struct RegexPattern {
+    /// The regex without the slashes, `\/` is unescaped to `/`
+    pub pattern: String,
+    pub flags: String,
+    regex: Regex,
+}
+
+impl RegexPattern {
+    pub fn new(pattern: &str, flags: &str) -> Result<Self, String> {
+        if let Some(flag) = flags.chars().find(|it| !"imsx".contains(*it)) {
+            return Err(format!("unknown regex flag `{}`", flag));
+        }
+
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(flags.contains('i'))
+            .multi_line(flags.contains('m'))
+            .dot_matches_new_line(flags.contains('s'))
+            .ignore_whitespace(flags.contains('x'))
+            .build()
+            .map_err(|_| format!("invalid regex `{}`", pattern))?;
+
+        Ok(RegexPattern { pattern: pattern.to_string(), flags: flags.to_string(), regex })
+    }
+
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+}
+
+/// The serialized form of [RegexPattern], the regex is compiled again when it's deserialized
+#[derive(Serialize, Deserialize)]
+struct RegexLiteral {
+    pattern: String,
+    flags: String,
+}
+
+impl From<RegexPattern> for RegexLiteral {
+    fn from(value: RegexPattern) -> Self {
+        RegexLiteral { pattern: value.pattern, flags: value.flags }
+    }
+}
+
+impl TryFrom<RegexLiteral> for RegexPattern {
+    type Error = String;
+
+    fn try_from(value: RegexLiteral) -> Result<Self, Self::Error> {
+        RegexPattern::new(&value.pattern, &value.flags)
+    }
+}
+
+// the compiled regex is derived from the pattern and flags, so they are enough to compare
+impl PartialEq for RegexPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern && self.flags == other.flags
+    }
+}
+
+impl Eq for RegexPattern {}
+
+impl fmt::Display for RegexPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "/{}/{}", self.pattern.replace('/', "\\/"), self.flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile_with_flags() {
+        let pattern = RegexPattern::new("src/main/.*\\.java", "").unwrap();
+        assert!(pattern.is_match("src/main/Foo.java"));
+        assert!(!pattern.is_match("src/main/Foo.kt"));
+        assert_eq!(pattern.to_string(), "/src\\/main\\/.*\\.java/");
+
+        let pattern = RegexPattern::new("^error$", "im").unwrap();
+        assert!(pattern.is_match("info\nERROR"));
+        assert_eq!(pattern.to_string(), "/^error$/im");
+    }
+
+    #[test]
+    fn test_reject_invalid_pattern() {
+        assert_eq!(RegexPattern::new("(", "").unwrap_err(), "invalid regex `(`");
+        assert_eq!(RegexPattern::new("error", "g").unwrap_err(), "unknown regex flag `g`");
+    }
+}
@@ -1,10 +1,12 @@
 use crate::ast::front_matter_type::FrontMatterType;
 use crate::ast::pattern_action_fun::PatternActionFunc;
-use std::any::Any;
-use std::collections::HashMap;
+use crate::compare::{compare, equals};
+use crate::runtime::{Environment, Value as RuntimeValue};
+use crate::span::{NodeSpan, Span};
 use std::fmt::Debug;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum StatementType {
     Operator(Operator),
     StringOperator(StringOperatorStatement),
@@ -12,6 +14,8 @@ pub enum StatementType {
     StringComparison(StringComparison),
     LogicalExpression(LogicalExpression),
     NotExpression(NotExpression),
+    BinaryExpression(BinaryExpression),
+    ConditionalExpression(ConditionalExpression),
     MethodCall(MethodCall),
     Value(Value),
     Processor(Processor),
@@ -20,114 +24,143 @@ pub enum StatementType {
 }
 
 pub trait Statement {
-    fn evaluate(&self, variables: &HashMap<String, String>) -> Result<Box<dyn std::any::Any>, String>;
+    fn evaluate(&self, env: &Environment) -> Result<RuntimeValue, String>;
     fn display(&self) -> String;
 }
 
 impl Statement for StatementType {
     // evaluate 函数
-    fn evaluate(&self, variables: &HashMap<String, String>) -> Result<Box<dyn std::any::Any>, String> {
+    fn evaluate(&self, env: &Environment) -> Result<RuntimeValue, String> {
         match &self {
-            StatementType::Operator(op) => Ok(Box::new(op.type_.display().clone())),
-            StatementType::StringOperator(op) => Ok(Box::new(op.type_.display().clone())),
-            StatementType::Comparison(comp) => Ok(Box::new(comp.evaluate(variables))),
-            StatementType::StringComparison(comp) => Ok(Box::new(comp.evaluate(variables))),
-            StatementType::LogicalExpression(expr) => Ok(Box::new(expr.evaluate(variables))),
-            StatementType::NotExpression(expr) => Ok(Box::new(expr.evaluate(variables))),
-            StatementType::MethodCall(call) => Ok(Box::new(call.evaluate(variables))),
-            StatementType::Value(val) => Ok(Box::new(val.evaluate(variables))),
-            StatementType::Processor(proc) => Ok(Box::new(proc.evaluate(variables))),
-            StatementType::CaseKeyValue(case) => Ok(Box::new(case.evaluate(variables))),
-            StatementType::ConditionCase(cond) => Ok(Box::new(cond.evaluate(variables))),
+            StatementType::Operator(op) => op.evaluate(env),
+            StatementType::StringOperator(op) => op.evaluate(env),
+            StatementType::Comparison(comp) => comp.evaluate(env),
+            StatementType::StringComparison(comp) => comp.evaluate(env),
+            StatementType::LogicalExpression(expr) => expr.evaluate(env),
+            StatementType::NotExpression(expr) => expr.evaluate(env),
+            StatementType::BinaryExpression(expr) => expr.evaluate(env),
+            StatementType::ConditionalExpression(expr) => expr.evaluate(env),
+            StatementType::MethodCall(call) => call.evaluate(env),
+            StatementType::Value(val) => val.evaluate(env),
+            StatementType::Processor(proc) => proc.evaluate(env),
+            StatementType::CaseKeyValue(case) => case.evaluate(env),
+            StatementType::ConditionCase(cond) => cond.evaluate(env),
         }
     }
 
     fn display(&self) -> String {
         match self {
-            StatementType::Operator(op) => format!("{}", op.type_.display()),
-            StatementType::StringOperator(op) => format!("{}", op.type_.display()),
-            StatementType::Comparison(comp) => format!(
-                "{} {} {}",
-                comp.left.display(),
-                comp.operator.type_.display(),
-                comp.right.display()
-            ),
-            StatementType::StringComparison(comp) => format!(
-                "{} {} {}",
-                comp.variable,
-                comp.operator.type_.display(),
-                comp.value
-            ),
-            StatementType::LogicalExpression(expr) => format!(
-                "{} {} {}",
-                expr.left.as_ref().display(),
-                expr.operator.display(),
-                expr.right.as_ref().display()
-            ),
-            StatementType::NotExpression(expr) => format!("!{}", expr.operand.as_ref().display()),
-            StatementType::MethodCall(call) => {
-                let parameters = call.arguments.as_ref().map(|args| {
-                    args.iter()
-                        .map(|arg| match arg {
-                            FrontMatterType::STRING(s) => s.clone(),
-                            _ => format!("{:?}", arg),
-                        })
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                }).unwrap_or_default();
-
-                let formatted_parameters = if parameters.is_empty() {
-                    "".to_string()
-                } else {
-                    format!("({})", parameters)
-                };
-
-                // let dot_with_target = if call.method_name == Box::from(FrontMatterType::EMPTY) {
-                //     "".to_string()
-                // } else if let FrontMatterType::IDENTIFIER(name) = &call.method_name {
-                //     if name.is_empty() {
-                //         "".to_string()
-                //     } else {
-                //         format!(".{}", call.method_name.display())
-                //     }
-                // } else {
-                //     format!(".{}", call.method_name.display())
-                // };
-                let dot_with_target = if call.method_name.display().is_empty() {
-                    "".to_string()
-                } else {
-                    format!(".{}", call.method_name.display())
-                };
-
-                format!(
-                    "{}{}{}",
-                    call.object_name.display(),
-                    dot_with_target,
-                    formatted_parameters
-                )
-            }
-            StatementType::Value(val) => val.value.display(),
-            StatementType::Processor(proc) => proc.processors.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(" | "),
-            _ => "Unsupported statement type".to_string(),
+            StatementType::Operator(op) => op.display(),
+            StatementType::StringOperator(op) => op.display(),
+            StatementType::Comparison(comp) => comp.display(),
+            StatementType::StringComparison(comp) => comp.display(),
+            StatementType::LogicalExpression(expr) => expr.display(),
+            StatementType::NotExpression(expr) => expr.display(),
+            StatementType::BinaryExpression(expr) => expr.display(),
+            StatementType::ConditionalExpression(expr) => expr.display(),
+            StatementType::MethodCall(call) => call.display(),
+            StatementType::Value(val) => val.display(),
+            StatementType::Processor(proc) => proc.display(),
+            StatementType::CaseKeyValue(case) => case.display(),
+            StatementType::ConditionCase(cond) => cond.display(),
+        }
+    }
+}
+
+impl StatementType {
+    /// The span of the parsed expression in source, which is empty for the statement created in code.
+    pub fn span(&self) -> Span {
+        match self {
+            StatementType::Comparison(comp) => comp.span.0,
+            StatementType::LogicalExpression(expr) => expr.span.0,
+            StatementType::NotExpression(expr) => expr.span.0,
+            StatementType::BinaryExpression(expr) => expr.span.0,
+            StatementType::ConditionalExpression(expr) => expr.span.0,
+            StatementType::MethodCall(call) => call.span.0,
+            StatementType::Value(val) => val.span.0,
+            _ => Span::default(),
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        match &mut self {
+            StatementType::Comparison(comp) => comp.span = NodeSpan(span),
+            StatementType::LogicalExpression(expr) => expr.span = NodeSpan(span),
+            StatementType::NotExpression(expr) => expr.span = NodeSpan(span),
+            StatementType::BinaryExpression(expr) => expr.span = NodeSpan(span),
+            StatementType::ConditionalExpression(expr) => expr.span = NodeSpan(span),
+            StatementType::MethodCall(call) => call.span = NodeSpan(span),
+            StatementType::Value(val) => val.span = NodeSpan(span),
+            _ => {}
+        }
+        self
+    }
+
+    /// The binding power of the statement, higher binds tighter:
+    /// `!` > `* / %` > `+ -` > `??` > comparison and `in` > `&&` > `||` > `?:`.
+    fn precedence(&self) -> u8 {
+        match self {
+            StatementType::ConditionalExpression(_) => 0,
+            StatementType::LogicalExpression(expr) => expr.operator.precedence(),
+            StatementType::Comparison(_) | StatementType::StringComparison(_) => 3,
+            StatementType::BinaryExpression(expr) => expr.operator.precedence(),
+            StatementType::NotExpression(_) => 7,
+            _ => 8,
+        }
+    }
+
+    /// Display the statement as an operand which requires `precedence`, add parentheses if it binds looser.
+    fn display_operand(&self, precedence: u8) -> String {
+        if self.precedence() < precedence {
+            format!("({})", self.display())
+        } else {
+            self.display()
         }
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// Evaluate the operand of comparison or method call, the variable is looked up and the expression is evaluated,
+/// the undefined variable is `null`.
+pub(crate) fn evaluate_operand(value: &FrontMatterType, env: &Environment) -> Result<RuntimeValue, String> {
+    match value {
+        FrontMatterType::VARIABLE(var) => Ok(env.get(var).unwrap_or(RuntimeValue::Null)),
+        // the bare name is bound by query, like `clazz` in `from { PsiClass clazz }`
+        FrontMatterType::IDENTIFIER(name) => Ok(env.get(name).unwrap_or_else(|| RuntimeValue::from(name.as_str()))),
+        FrontMatterType::EXPRESSION(statement) => statement.evaluate(env),
+        FrontMatterType::CASE_MATCH(case) => case.evaluate(env),
+        FrontMatterType::ARRAY(values) => {
+            Ok(RuntimeValue::List(values.iter().map(|it| evaluate_operand(it, env)).collect::<Result<_, _>>()?))
+        }
+        value => value.to_value(),
+    }
+}
+
+fn display_operand(value: &FrontMatterType, precedence: u8) -> String {
+    match value {
+        FrontMatterType::EXPRESSION(statement) => statement.display_operand(precedence),
+        _ => value.display(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Value {
     value: Box<FrontMatterType>,
+    span: NodeSpan,
+}
+
+impl Value {
+    pub fn new(value: FrontMatterType) -> Self {
+        Value { value: Box::new(value), span: NodeSpan::default() }
+    }
+
+    pub fn value(&self) -> &FrontMatterType {
+        &self.value
+    }
 }
 
 impl Statement for Value {
-    fn evaluate(&self, _variables: &HashMap<String, String>) -> Result<Box<dyn std::any::Any>, String> {
-        let result: Box<dyn std::any::Any> = match &self.value.as_ref() {
-            FrontMatterType::STRING(val) => Box::new(val.clone()),
-            FrontMatterType::NUMBER(val) => Box::new(*val),
-            FrontMatterType::DATE(val) => Box::new(val.clone()),
-            FrontMatterType::BOOLEAN(val) => Box::new(*val),
-            _ => return Err(format!("Unsupported value type: {:?}", self.value)),
-        };
-        Ok(result)
+    fn evaluate(&self, env: &Environment) -> Result<RuntimeValue, String> {
+        evaluate_operand(&self.value, env)
     }
 
     fn display(&self) -> String {
@@ -135,7 +168,7 @@ impl Statement for Value {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum OperatorType {
     Or,
     And,
@@ -146,10 +179,20 @@ pub enum OperatorType {
     GreaterThan,
     LessEqual,
     GreaterEqual,
+    /// The membership of list, object key or substring, for example: `$lang in ["kotlin", "java"]`
+    In,
+    /// The addition or concatenation, for example: `$size + 1` or `$name + ".kt"`
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    /// The null-coalescing, for example: `$language ?? "kotlin"`
+    Coalesce,
 }
 
 impl OperatorType {
-    fn from_str(operator: &str) -> Result<Self, String> {
+    pub(crate) fn from_str(operator: &str) -> Result<Self, String> {
         match operator {
             "||" => Ok(OperatorType::Or),
             "&&" => Ok(OperatorType::And),
@@ -160,33 +203,60 @@ impl OperatorType {
             ">" => Ok(OperatorType::GreaterThan),
             "<=" => Ok(OperatorType::LessEqual),
             ">=" => Ok(OperatorType::GreaterEqual),
+            "in" => Ok(OperatorType::In),
+            "+" => Ok(OperatorType::Add),
+            "-" => Ok(OperatorType::Subtract),
+            "*" => Ok(OperatorType::Multiply),
+            "/" => Ok(OperatorType::Divide),
+            "%" => Ok(OperatorType::Modulo),
+            "??" => Ok(OperatorType::Coalesce),
             _ => Err(format!("Invalid operator: {}", operator)),
         }
     }
+
+    /// The binding power of binary operator, higher binds tighter.
+    pub(crate) fn precedence(&self) -> u8 {
+        match self {
+            OperatorType::Or => 1,
+            OperatorType::And => 2,
+            OperatorType::Coalesce => 4,
+            OperatorType::Add | OperatorType::Subtract => 5,
+            OperatorType::Multiply | OperatorType::Divide | OperatorType::Modulo => 6,
+            OperatorType::Not => 7,
+            _ => 3,
+        }
+    }
 }
 
 impl Statement for OperatorType {
-    fn evaluate(&self, variables: &HashMap<String, String>) -> Result<Box<dyn Any>, String> {
-        Ok(Box::new(self.display().to_string()))
+    fn evaluate(&self, _env: &Environment) -> Result<RuntimeValue, String> {
+        Ok(RuntimeValue::from(self.display()))
     }
 
     fn display(&self) -> String {
         match self {
-            OperatorType::Or => format!("{}", "||"),
-            OperatorType::And => format!("{}", "&&"),
-            OperatorType::Not => format!("{}", "!"),
-            OperatorType::Equal => format!("{}", "=="),
-            OperatorType::NotEqual => format!("{}", "!="),
-            OperatorType::LessThan => format!("{}", "<"),
-            OperatorType::GreaterThan => format!("{}", ">"),
-            OperatorType::LessEqual => format!("{}", "<="),
-            OperatorType::GreaterEqual => format!("{}", ">="),
+            OperatorType::Or => "||".to_string(),
+            OperatorType::And => "&&".to_string(),
+            OperatorType::Not => "!".to_string(),
+            OperatorType::Equal => "==".to_string(),
+            OperatorType::NotEqual => "!=".to_string(),
+            OperatorType::LessThan => "<".to_string(),
+            OperatorType::GreaterThan => ">".to_string(),
+            OperatorType::LessEqual => "<=".to_string(),
+            OperatorType::GreaterEqual => ">=".to_string(),
+            OperatorType::In => "in".to_string(),
+            OperatorType::Add => "+".to_string(),
+            OperatorType::Subtract => "-".to_string(),
+            OperatorType::Multiply => "*".to_string(),
+            OperatorType::Divide => "/".to_string(),
+            OperatorType::Modulo => "%".to_string(),
+            OperatorType::Coalesce => "??".to_string(),
         }
     }
 
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum StringOperator {
     Contains,
     StartsWith,
@@ -195,8 +265,8 @@ pub enum StringOperator {
 }
 
 impl Statement for StringOperator {
-    fn evaluate(&self, variables: &HashMap<String, String>) -> Result<Box<dyn Any>, String> {
-        Ok(Box::new(self.display().to_string()))
+    fn evaluate(&self, _env: &Environment) -> Result<RuntimeValue, String> {
+        Ok(RuntimeValue::from(self.display()))
     }
 
     fn display(&self) -> String {
@@ -209,14 +279,20 @@ impl Statement for StringOperator {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Operator {
     type_: OperatorType,
 }
 
+impl Operator {
+    pub fn new(type_: OperatorType) -> Self {
+        Operator { type_ }
+    }
+}
+
 impl Statement for Operator {
-    fn evaluate(&self, _variables: &HashMap<String, String>) -> Result<Box<dyn std::any::Any>, String> {
-        Ok(Box::new(self.type_.display().to_string()))
+    fn evaluate(&self, _env: &Environment) -> Result<RuntimeValue, String> {
+        Ok(RuntimeValue::from(self.type_.display()))
     }
 
     fn display(&self) -> String {
@@ -224,14 +300,14 @@ impl Statement for Operator {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct StringOperatorStatement {
     type_: StringOperator,
 }
 
 impl Statement for StringOperatorStatement {
-    fn evaluate(&self, _variables: &HashMap<String, String>) -> Result<Box<dyn std::any::Any>, String> {
-        Ok(Box::new(self.type_.display().to_string()))
+    fn evaluate(&self, _env: &Environment) -> Result<RuntimeValue, String> {
+        Ok(RuntimeValue::from(self.type_.display()))
     }
 
     fn display(&self) -> String {
@@ -239,45 +315,56 @@ impl Statement for StringOperatorStatement {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Comparison {
     left: Box<FrontMatterType>,
     operator: Operator,
     right: Box<FrontMatterType>,
+    span: NodeSpan,
 }
 
-impl Statement for Comparison {
-    fn evaluate(&self, variables: &HashMap<String, String>) -> Result<Box<dyn std::any::Any>, String> {
-        let left_value = match &self.left.as_ref() {
-            FrontMatterType::STRING(val) => val.clone(),
-            FrontMatterType::VARIABLE(var) => variables.get(var).cloned().unwrap_or_else(|| "".to_string()),
-            _ => return Err("Unsupported left value type".to_string()),
-        };
+impl Comparison {
+    pub fn new(left: FrontMatterType, operator: Operator, right: FrontMatterType) -> Self {
+        Comparison {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+            span: NodeSpan::default(),
+        }
+    }
+}
 
-        let right_value = match &self.right.as_ref() {
-            FrontMatterType::STRING(val) => val.clone(),
-            _ => return Err("Unsupported right value type".to_string()),
-        };
+impl Statement for Comparison {
+    fn evaluate(&self, env: &Environment) -> Result<RuntimeValue, String> {
+        let left_value = evaluate_operand(&self.left, env)?;
+        let right_value = evaluate_operand(&self.right, env)?;
 
+        let ordering = || compare(&left_value, &right_value);
         let result = match self.operator.type_ {
-            OperatorType::Equal => left_value == right_value,
-            OperatorType::NotEqual => left_value != right_value,
-            OperatorType::LessThan => left_value < right_value,
-            OperatorType::GreaterThan => left_value > right_value,
-            OperatorType::LessEqual => left_value <= right_value,
-            OperatorType::GreaterEqual => left_value >= right_value,
+            OperatorType::Equal => equals(&left_value, &right_value),
+            OperatorType::NotEqual => !equals(&left_value, &right_value),
+            OperatorType::LessThan => ordering()?.is_lt(),
+            OperatorType::GreaterThan => ordering()?.is_gt(),
+            OperatorType::LessEqual => ordering()?.is_le(),
+            OperatorType::GreaterEqual => ordering()?.is_ge(),
+            OperatorType::In => right_value.contains(&left_value)?,
             _ => return Err("Invalid comparison operator".to_string()),
         };
 
-        Ok(Box::new(result))
+        Ok(RuntimeValue::Bool(result))
     }
 
     fn display(&self) -> String {
-        format!("{} {} {}", self.left.display(), self.operator.display(), self.right.display())
+        format!(
+            "{} {} {}",
+            display_operand(&self.left, 4),
+            self.operator.display(),
+            display_operand(&self.right, 4)
+        )
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct StringComparison {
     variable: String,
     operator: StringOperatorStatement,
@@ -285,7 +372,7 @@ pub struct StringComparison {
 }
 
 impl Statement for StringComparison {
-    fn evaluate(&self, _variables: &HashMap<String, String>) -> Result<Box<dyn std::any::Any>, String> {
+    fn evaluate(&self, _env: &Environment) -> Result<RuntimeValue, String> {
         let result = match self.operator.type_ {
             StringOperator::Contains => self.variable.contains(&self.value),
             StringOperator::StartsWith => self.variable.starts_with(&self.value),
@@ -298,7 +385,7 @@ impl Statement for StringComparison {
             }
         };
 
-        Ok(Box::new(result))
+        Ok(RuntimeValue::Bool(result))
     }
 
     fn display(&self) -> String {
@@ -306,186 +393,244 @@ impl Statement for StringComparison {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LogicalExpression {
     left: Box<StatementType>,
     operator: OperatorType,
     right: Box<StatementType>,
+    span: NodeSpan,
 }
 
-impl Statement for LogicalExpression {
-    fn evaluate(&self, variables: &HashMap<String, String>) -> Result<Box<dyn std::any::Any>, String> {
-        // Evaluate the left and right operands
-        let left_result = self.left.as_ref().evaluate(variables);
-        let right_result = self.right.as_ref().evaluate(variables);
-
-        // Downcast the results to booleans
-        let left = left_result?;
-        let left_value = match left.downcast_ref::<bool>() {
-            Some(value) => value,
-            None => return Err("Left operand is not of type bool".to_string()),
-        };
-
-
-        let right = right_result?;
-        let right_value = match right.downcast_ref::<bool>() {
-            Some(value) => value,
-            None => return Err("Right operand is not of type bool".to_string()),
-        };
+impl LogicalExpression {
+    pub fn new(left: StatementType, operator: OperatorType, right: StatementType) -> Self {
+        LogicalExpression {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+            span: NodeSpan::default(),
+        }
+    }
+}
 
-        // Compute the result based on the operator
+impl Statement for LogicalExpression {
+    fn evaluate(&self, env: &Environment) -> Result<RuntimeValue, String> {
+        let left_value = self.left.evaluate(env)?.expect_bool("left operand")?;
+        // the right operand is only evaluated if the left one doesn't decide the result
         let result = match self.operator {
-            OperatorType::And => *left_value && *right_value,
-            OperatorType::Or => *left_value || *right_value,
+            OperatorType::And if !left_value => false,
+            OperatorType::Or if left_value => true,
+            OperatorType::And | OperatorType::Or => self.right.evaluate(env)?.expect_bool("right operand")?,
             _ => return Err("Invalid logical operator".to_string()),
         };
 
-        // Return the result as a Box<dyn Any> wrapped in Ok
-        Ok(Box::new(result))
+        Ok(RuntimeValue::Bool(result))
     }
 
     fn display(&self) -> String {
-        format!("{} {} {}", self.left.as_ref().display(), self.operator.display(), self.right.as_ref().display())
+        let precedence = self.operator.precedence();
+        format!(
+            "{} {} {}",
+            self.left.display_operand(precedence),
+            self.operator.display(),
+            self.right.display_operand(precedence + 1)
+        )
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NotExpression {
     operand: Box<StatementType>,
+    span: NodeSpan,
+}
+
+impl NotExpression {
+    pub fn new(operand: StatementType) -> Self {
+        NotExpression { operand: Box::new(operand), span: NodeSpan::default() }
+    }
 }
 
 impl Statement for NotExpression {
-    fn evaluate(&self, variables: &HashMap<String, String>) -> Result<Box<dyn std::any::Any>, String> {
-        // Evaluate the operand and get the result as a Box<dyn Any>
-        let operand_result = self.operand.as_ref().evaluate(variables);
-
-        // Attempt to downcast the result to a boolean
-        let op = operand_result?;
-        let operand_value = match op.downcast_ref::<bool>() {
-            Some(value) => value,
-            None => return Err("Operand is not of type bool".to_string()),
-        };
+    fn evaluate(&self, env: &Environment) -> Result<RuntimeValue, String> {
+        let operand_value = self.operand.evaluate(env)?.expect_bool("operand of `!`")?;
+        Ok(RuntimeValue::Bool(!operand_value))
+    }
+
+    fn display(&self) -> String {
+        format!("!{}", self.operand.display_operand(7))
+    }
+}
+
+/// The arithmetic or null-coalescing expression, for example: `$size * 2 + 1` or `$language ?? "kotlin"`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BinaryExpression {
+    left: Box<FrontMatterType>,
+    operator: OperatorType,
+    right: Box<FrontMatterType>,
+    span: NodeSpan,
+}
+
+impl BinaryExpression {
+    pub fn new(left: FrontMatterType, operator: OperatorType, right: FrontMatterType) -> Self {
+        BinaryExpression {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+            span: NodeSpan::default(),
+        }
+    }
+}
 
-        // Compute the negation of the boolean value
-        let result = !*operand_value;
+impl Statement for BinaryExpression {
+    fn evaluate(&self, env: &Environment) -> Result<RuntimeValue, String> {
+        let left_value = evaluate_operand(&self.left, env)?;
+        // the right operand of `??` is evaluated only if the left is null
+        if self.operator == OperatorType::Coalesce {
+            return match left_value {
+                RuntimeValue::Null => evaluate_operand(&self.right, env),
+                value => Ok(value),
+            };
+        }
+
+        let right_value = evaluate_operand(&self.right, env)?;
+        match self.operator {
+            OperatorType::Add => left_value.add(&right_value),
+            OperatorType::Subtract => left_value.subtract(&right_value),
+            OperatorType::Multiply => left_value.multiply(&right_value),
+            OperatorType::Divide => left_value.divide(&right_value),
+            OperatorType::Modulo => left_value.remainder(&right_value),
+            _ => Err("Invalid arithmetic operator".to_string()),
+        }
+    }
+
+    fn display(&self) -> String {
+        let precedence = self.operator.precedence();
+        format!(
+            "{} {} {}",
+            display_operand(&self.left, precedence),
+            self.operator.display(),
+            display_operand(&self.right, precedence + 1)
+        )
+    }
+}
+
+/// The ternary expression, for example: `$size > 100 ? "large" : "small"`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConditionalExpression {
+    condition: Box<StatementType>,
+    then: Box<FrontMatterType>,
+    otherwise: Box<FrontMatterType>,
+    span: NodeSpan,
+}
 
-        // Return the result as a Box<dyn Any> wrapped in Ok
-        Ok(Box::new(result))
+impl ConditionalExpression {
+    pub fn new(condition: StatementType, then: FrontMatterType, otherwise: FrontMatterType) -> Self {
+        ConditionalExpression {
+            condition: Box::new(condition),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+            span: NodeSpan::default(),
+        }
+    }
+}
+
+impl Statement for ConditionalExpression {
+    fn evaluate(&self, env: &Environment) -> Result<RuntimeValue, String> {
+        match self.condition.evaluate(env)?.expect_bool("condition of `?:`")? {
+            true => evaluate_operand(&self.then, env),
+            false => evaluate_operand(&self.otherwise, env),
+        }
     }
 
     fn display(&self) -> String {
-        format!("!{}", self.operand.as_ref().display())
+        // the nested ternary is right associative, so only the one in condition or `then` needs parentheses
+        format!(
+            "{} ? {} : {}",
+            self.condition.display_operand(1),
+            display_operand(&self.then, 1),
+            display_operand(&self.otherwise, 0)
+        )
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// The call of method or property, the object can be another call for chains like `$a.lowercase().endsWith(".kt")`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MethodCall {
     object_name: Box<FrontMatterType>,
     method_name: Box<FrontMatterType>,
     arguments: Option<Vec<FrontMatterType>>,
+    span: NodeSpan,
 }
 
 impl MethodCall {
-    fn parameters(&self) -> Option<Vec<String>> {
-        self.arguments.as_ref().map(|args| {
-            args.iter()
-                .map(|arg| match arg {
-                    FrontMatterType::STRING(s) => s.clone(),
-                    _ => arg.display(),
-                })
-                .collect()
-        })
-    }
-
-    fn evaluate_expression(
-        &self,
-        method_name: &str,
-        parameters: Option<Vec<String>>,
-        value: &str,
-    ) -> Box<dyn std::any::Any> {
-        match method_name {
-            "length" => Box::new(value.len()),
-            "trim" => Box::new(value.trim().to_string()),
-            "contains" => {
-                // let param = parameters.unwrap().get(0).unwrap();
-                let params = parameters.unwrap();
-                let param = params.get(0).unwrap(); // This is now a longer-lived value
-
-                Box::new(value.contains(param))
-            }
-            "startsWith" => {
-                // let param = parameters.unwrap().get(0).unwrap();
-                let params = parameters.unwrap();
-                let param = params.get(0).unwrap(); // This is now a longer-lived value
-
-                Box::new(value.starts_with(param))
-            }
-            "endsWith" => {
-                // let param = parameters.unwrap().get(0).unwrap();
-                let params = parameters.unwrap();
-                let param = params.get(0).unwrap(); // This is now a longer-lived value
+    pub fn new(object_name: FrontMatterType, method_name: FrontMatterType, arguments: Option<Vec<FrontMatterType>>) -> Self {
+        MethodCall {
+            object_name: Box::new(object_name),
+            method_name: Box::new(method_name),
+            arguments,
+            span: NodeSpan::default(),
+        }
+    }
 
-                Box::new(value.ends_with(param))
-            }
-            "lowercase" => Box::new(value.to_lowercase()),
-            "uppercase" => Box::new(value.to_uppercase()),
-            "isEmpty" => Box::new(value.is_empty()),
-            "isNotEmpty" => Box::new(!value.is_empty()),
-            "first" => Box::new(value.chars().next().unwrap().to_string()),
-            "last" => Box::new(value.chars().last().unwrap().to_string()),
-            "matches" => {
-                // let param = parameters.unwrap().get(0).unwrap();
-                let params = parameters.unwrap();
-                let param = params.get(0).unwrap(); // This is now a longer-lived value
-
-                let regex = regex::Regex::new(param).unwrap();
-                Box::new(regex.is_match(value))
+    /// The member read by the call, the property `$file.name` and the getter `clazz.getName()` both read `name`
+    fn member_name(&self) -> Option<String> {
+        let name = self.method_name.display();
+        match &self.arguments {
+            None => Some(name),
+            Some(args) if args.is_empty() => {
+                let property = name.strip_prefix("get").filter(|it| it.starts_with(char::is_uppercase))?;
+                let mut chars = property.chars();
+                chars.next().map(|first| first.to_lowercase().chain(chars).collect())
             }
-            _ => panic!("Unsupported method: {}", method_name),
+            Some(_) => None,
         }
     }
 }
 
 impl Statement for MethodCall {
-    fn evaluate(&self, variables: &HashMap<String, String>) -> Result<Box<dyn std::any::Any>, String> {
-        // Resolve the object name to a string value
-        let value = match &self.object_name.as_ref() {
-            FrontMatterType::STRING(s) => s.clone(),
-            FrontMatterType::VARIABLE(var) => variables.get(var).cloned().unwrap_or_else(|| "".to_string()),
-            _ => return Err("Unsupported object name type".to_string()),
-        };
+    fn evaluate(&self, env: &Environment) -> Result<RuntimeValue, String> {
+        // the object can be the result of previous call in chain, like `$a.lowercase()`
+        let object = evaluate_operand(&self.object_name, env)?;
+        if let Some(member) = self.member_name().and_then(|name| object.member(&name)) {
+            return Ok(member);
+        }
 
-        // Prepare method name and parameters
-        let method_name = self.method_name.display();
-        let parameters = self.parameters();
+        // the undefined variable is empty string, like `$a.isEmpty()`
+        let receiver = match object {
+            RuntimeValue::Null => RuntimeValue::from(""),
+            object => object,
+        };
+        let arguments = self.arguments.iter().flatten().map(|arg| evaluate_operand(arg, env)).collect::<Result<Vec<_>, _>>()?;
 
-        // Evaluate the expression and handle potential errors
-        // self.evaluate_expression(&method_name, parameters, &value)
-        //     .map(|result| Box::new(result) as Box<dyn std::any::Any>)
-        //     .map_err(|e| e.to_string())
-        Ok(Box::new(self.evaluate_expression(&method_name, parameters, &value)))
+        env.methods().call(&self.method_name.display(), &receiver, &arguments).map_err(|err| err.to_string())
     }
 
     fn display(&self) -> String {
-        let parameters = self.parameters().map(|params| {
-            format!("({})", params.join(", "))
+        let parameters = self.arguments.as_ref().map(|args| {
+            format!("({})", args.iter().map(|arg| arg.display()).collect::<Vec<_>>().join(", "))
         }).unwrap_or_default();
 
-        format!("{}{}{}", self.object_name.display(), self.method_name.display(), parameters)
+        format!("{}.{}{}", display_operand(&self.object_name, 8), self.method_name.display(), parameters)
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Processor {
     processors: Vec<PatternActionFunc>,
 }
 
-impl Statement for Processor {
-    fn evaluate(&self, _variables: &HashMap<String, String>) -> Result<Box<dyn std::any::Any>, String> {
-        let processors = self.processors.clone();
+impl Processor {
+    pub fn new(processors: Vec<PatternActionFunc>) -> Self {
+        Processor { processors }
+    }
 
-        // Convert Vec<PatternActionFunc> to Box<dyn std::any::Any>
-        Ok(Box::new(processors) as Box<dyn std::any::Any>)
+    pub fn processors(&self) -> &[PatternActionFunc] {
+        &self.processors
+    }
+}
+
+impl Statement for Processor {
+    fn evaluate(&self, _env: &Environment) -> Result<RuntimeValue, String> {
+        Ok(RuntimeValue::Pipeline(self.processors.clone()))
     }
 
     fn display(&self) -> String {
@@ -494,52 +639,159 @@ impl Statement for Processor {
 }
 
 // CaseKeyValue 结构体
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// The arm of case block, the key is glob like `"*.java"`, regex like `/error/i` or `default`,
+/// and the value is the pipeline or the nested case block.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CaseKeyValue {
     key: Box<FrontMatterType>,
     value: Box<FrontMatterType>,
 }
 
-impl Statement for CaseKeyValue {
-    fn evaluate(&self, _variables: &HashMap<String, String>) -> Result<Box<dyn std::any::Any>, String> {
-        // Create the tuple from the key and value
-        let result = (
-            self.key.display(),
-            self.value.display()
-        );
+impl CaseKeyValue {
+    pub fn new(key: FrontMatterType, value: FrontMatterType) -> Self {
+        CaseKeyValue {
+            key: Box::new(key),
+            value: Box::new(value),
+        }
+    }
+
+    pub fn key(&self) -> &FrontMatterType {
+        &self.key
+    }
 
-        // Return the tuple boxed as Box<dyn Any>
-        Ok(Box::new(result) as Box<dyn std::any::Any>)
+    pub fn value(&self) -> &FrontMatterType {
+        &self.value
+    }
+
+    /// Whether the arm matches `subject`, the glob matches the whole subject while the regex matches any part,
+    /// and `default` matches anything.
+    pub fn matches(&self, subject: &str) -> bool {
+        match self.key.as_ref() {
+            FrontMatterType::STRING(glob) => glob_match(glob, subject),
+            FrontMatterType::REGEX(regex) => regex.is_match(subject),
+            FrontMatterType::IDENTIFIER(name) => name == "default",
+            _ => false,
+        }
+    }
+
+    /// Define the variables of arm matching `subject`, `$0` is the subject and `$1`, `$2`... are the groups
+    /// captured by regex, for example: `/(\w+)\.kt/ { case "$1" { ... } }`
+    pub fn bind(&self, subject: &str, scope: &mut Environment) {
+        scope.define("0", subject);
+        if let FrontMatterType::REGEX(regex) = self.key.as_ref() {
+            let Some(captures) = regex.regex().captures(subject) else { return };
+            for (index, group) in captures.iter().enumerate().skip(1) {
+                scope.define(index.to_string(), group.map(|it| it.as_str()));
+            }
+        }
+    }
+}
+
+/// Match `text` with `pattern`, where `*` matches any chars and `?` matches one char
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // the position of last `*` and the text it has matched to, for backtracking
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn indent(text: &str) -> String {
+    text.lines().map(|line| format!("  {}", line)).collect::<Vec<_>>().join("\n")
+}
+
+impl Statement for CaseKeyValue {
+    fn evaluate(&self, _env: &Environment) -> Result<RuntimeValue, String> {
+        // the pair of key and value
+        Ok(RuntimeValue::from(vec![self.key.display(), self.value.display()]))
     }
 
     fn display(&self) -> String {
-        format!("\"{}\" -> {}", self.key.display(), self.value.display())
+        match self.value.as_ref() {
+            FrontMatterType::EXPRESSION(StatementType::ConditionCase(cond)) => {
+                format!("{} {{\n{}\n}}", self.key.display(), indent(&cond.display()))
+            }
+            value => format!("{} {{ {} }}", self.key.display(), value.display()),
+        }
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// The case block which takes the first arm matching the subject, for example:
+/// ```shire
+/// case "$fileName" {
+///   "*.java" { grep("class") }
+///   /.*\.kts?/i { grep("fun") }
+///   default { print("unknown") }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ConditionCase {
-    conditions: Vec<FrontMatterType>,
-    cases: Vec<FrontMatterType>,
+    subject: Box<FrontMatterType>,
+    cases: Vec<CaseKeyValue>,
 }
 
-impl Statement for ConditionCase {
-    fn evaluate(&self, _variables: &HashMap<String, String>) -> Result<Box<dyn std::any::Any>, String> {
-        // Create vectors of strings from the conditions and cases
-        let condition: Vec<String> = self.conditions.iter().map(|cond| cond.display()).collect();
-        let case: Vec<String> = self.cases.iter().map(|case| case.display()).collect();
+impl ConditionCase {
+    pub fn new(subject: FrontMatterType, cases: Vec<CaseKeyValue>) -> Self {
+        ConditionCase { subject: Box::new(subject), cases }
+    }
 
-        // Create the tuple of vectors
-        let result = (condition, case);
+    pub fn subject(&self) -> &FrontMatterType {
+        &self.subject
+    }
 
-        // Box the tuple and return it
-        Ok(Box::new(result) as Box<dyn std::any::Any>)
+    pub fn cases(&self) -> &[CaseKeyValue] {
+        &self.cases
     }
 
-    fn display(&self) -> String {
-        let conditions = self.conditions.iter().map(|cond| cond.display()).collect::<Vec<_>>().join(", ");
-        let cases = self.cases.iter().map(|case| case.display()).collect::<Vec<_>>().join(", ");
+    /// The first arm matching `subject`, returns `None` if nothing matches and there is no `default`
+    pub fn find(&self, subject: &str) -> Option<&CaseKeyValue> {
+        self.cases.iter().find(|case| case.matches(subject))
+    }
+}
+
+impl Statement for ConditionCase {
+    fn evaluate(&self, env: &Environment) -> Result<RuntimeValue, String> {
+        // the subject is a variable like `$fileName` or the text quoted like `"$fileName"`
+        let subject = match self.subject.as_ref() {
+            FrontMatterType::VARIABLE(name) => env.get(name).map(|it| it.to_string()).unwrap_or_default(),
+            FrontMatterType::STRING(text) => match text.strip_prefix('$') {
+                Some(name) => env.get(name).map(|it| it.to_string()).unwrap_or_default(),
+                None => text.clone(),
+            },
+            subject => subject.display(),
+        };
+
+        let case = self.find(&subject).ok_or_else(|| format!("no case matches `{}`", subject))?;
+        let mut scope = env.child();
+        case.bind(&subject, &mut scope);
+        evaluate_operand(case.value(), &scope)
+    }
 
-        format!("case \"{}\" -> {}", conditions, cases)
+    fn display(&self) -> String {
+        let cases = self.cases.iter().map(|case| indent(&case.display())).collect::<Vec<_>>().join("\n");
+        format!("case {} {{\n{}\n}}", self.subject.display(), cases)
     }
 }
@@ -0,0 +1,248 @@
+use crate::span::Span;
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_until, take_while, take_while1},
+    character::complete::{char, line_ending, not_line_ending, space0},
+    combinator::{eof, map, map_opt, opt, rest},
+    sequence::{delimited, preceded, terminated, tuple},
+    IResult,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BodyNodeKind {
+    /// Plain text which is rendered as it is, including the line breaks
+    Text(String),
+    /// Variable reference, for example: `$selection` or `${selection}`
+    Variable(String),
+    /// Command line, for example: `/file:src/main.rs#L1-L20` or `/commit`
+    Command { name: String, argument: Option<String> },
+    /// Agent mention, for example: `@reviewer`
+    Agent(String),
+    /// Toolchain reference, for example: `#kotlin`
+    Toolchain(String),
+    /// Fenced code block, the code is kept as it is without resolving variables
+    CodeBlock { language: String, code: String },
+    /// Comment line, for example: `[#]: # (explain in Chinese)`, which is stripped from the prompt
+    Comment(String),
+}
+
+/// The node in the body of Shire file, which is the prompt after the front matter.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BodyNode {
+    pub kind: BodyNodeKind,
+    pub span: Span,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+fn parse_name(input: &str) -> IResult<&str, String> {
+    map(take_while1(is_name_char), |s: &str| s.to_string())(input)
+}
+
+fn parse_variable_name(input: &str) -> IResult<&str, String> {
+    map(
+        tuple((
+            take_while1(|c: char| c.is_alphabetic() || c == '_'),
+            take_while(|c: char| c.is_alphanumeric() || c == '_'),
+        )),
+        |(head, tail): (&str, &str)| format!("{}{}", head, tail),
+    )(input)
+}
+
+/// Parser for variable reference, for example: `$selection` or `${selection}`
+fn parse_variable_reference(input: &str) -> IResult<&str, BodyNodeKind> {
+    map(
+        preceded(
+            char('$'),
+            alt((delimited(char('{'), parse_variable_name, char('}')), parse_variable_name)),
+        ),
+        BodyNodeKind::Variable,
+    )(input)
+}
+
+/// Parser for command line, for example: `/file:src/main.rs#L1-L20`
+fn parse_command(input: &str) -> IResult<&str, BodyNodeKind> {
+    let (input, name) = preceded(char('/'), parse_name)(input)?;
+    let (input, argument) = opt(preceded(char(':'), take_while1(|c: char| !c.is_whitespace())))(input)?;
+    Ok((input, BodyNodeKind::Command { name, argument: argument.map(|s| s.to_string()) }))
+}
+
+/// Parser for fenced code block, the unclosed block ends at the end of body like markdown
+fn parse_code_block(input: &str) -> IResult<&str, BodyNodeKind> {
+    let (input, language) = delimited(tag("```"), not_line_ending, alt((line_ending, eof)))(input)?;
+    let (input, code) = alt((
+        terminated(take_until("```"), tuple((tag("```"), not_line_ending))),
+        rest,
+    ))(input)?;
+
+    let code = code.strip_suffix('\n').unwrap_or(code);
+    Ok((input, BodyNodeKind::CodeBlock {
+        language: language.trim().to_string(),
+        code: code.strip_suffix('\r').unwrap_or(code).to_string(),
+    }))
+}
+
+/// Parser for comment line like markdown reference link, for example: `[#]: # (TODO)` or `[//]: # (TODO)`,
+/// the line break is part of the comment, so no blank line is left after stripping.
+fn parse_comment(input: &str) -> IResult<&str, BodyNodeKind> {
+    map_opt(
+        delimited(
+            tuple((char('['), alt((tag("#"), tag("//"))), tag("]:"), space0, char('#'), space0, char('('))),
+            not_line_ending,
+            alt((line_ending, eof)),
+        ),
+        |text: &str| text.trim_end().strip_suffix(')').map(|text| BodyNodeKind::Comment(text.to_string())),
+    )(input)
+}
+
+/// The prompt of body with comments stripped, other nodes are kept as their text in `source`.
+pub fn strip_comments(source: &str, nodes: &[BodyNode]) -> String {
+    nodes
+        .iter()
+        .filter(|node| !matches!(node.kind, BodyNodeKind::Comment(_)))
+        .map(|node| &source[node.span.start..node.span.end])
+        .collect()
+}
+
+/// Parse the body into nodes, `offset` is the position of body in the source which is added to the spans.
+///
+/// Commands and code blocks are only recognized at the start of line, agents and toolchains only after
+/// whitespace, so that text like `a@b.com` or `# Title` stays plain text. Comments are kept as nodes
+/// for formatters, use [strip_comments] to render the prompt.
+pub fn parse_body(input: &str, offset: usize) -> Vec<BodyNode> {
+    let mut nodes = vec![];
+    let mut text_start = 0;
+    let mut index = 0;
+
+    while let Some(current) = input[index..].chars().next() {
+        let line_start = index == 0 || input[..index].ends_with('\n');
+        let after_whitespace = index == 0 || input[..index].ends_with(char::is_whitespace);
+
+        let remaining = &input[index..];
+        let parsed = match current {
+            '`' if line_start => parse_code_block(remaining).ok(),
+            '[' if line_start => parse_comment(remaining).ok(),
+            '/' if line_start => parse_command(remaining).ok(),
+            '$' => parse_variable_reference(remaining).ok(),
+            '@' if after_whitespace => map(preceded(char('@'), parse_name), BodyNodeKind::Agent)(remaining).ok(),
+            '#' if after_whitespace => map(preceded(char('#'), parse_name), BodyNodeKind::Toolchain)(remaining).ok(),
+            _ => None,
+        };
+
+        match parsed {
+            Some((remaining, kind)) => {
+                if text_start < index {
+                    nodes.push(BodyNode {
+                        kind: BodyNodeKind::Text(input[text_start..index].to_string()),
+                        span: Span::new(offset + text_start, offset + index),
+                    });
+                }
+
+                let end = input.len() - remaining.len();
+                nodes.push(BodyNode { kind, span: Span::new(offset + index, offset + end) });
+                index = end;
+                text_start = end;
+            }
+            None => index += current.len_utf8(),
+        }
+    }
+
+    if text_start < input.len() {
+        nodes.push(BodyNode {
+            kind: BodyNodeKind::Text(input[text_start..].to_string()),
+            span: Span::new(offset + text_start, offset + input.len()),
+        });
+    }
+
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<BodyNodeKind> {
+        parse_body(input, 0).into_iter().map(|node| node.kind).collect()
+    }
+
+    #[test]
+    fn test_parse_variables() {
+        let nodes = parse_body("Explain $selection and ${fileName}!", 10);
+        assert_eq!(
+            nodes,
+            vec![
+                BodyNode { kind: BodyNodeKind::Text("Explain ".to_string()), span: Span::new(10, 18) },
+                BodyNode { kind: BodyNodeKind::Variable("selection".to_string()), span: Span::new(18, 28) },
+                BodyNode { kind: BodyNodeKind::Text(" and ".to_string()), span: Span::new(28, 33) },
+                BodyNode { kind: BodyNodeKind::Variable("fileName".to_string()), span: Span::new(33, 44) },
+                BodyNode { kind: BodyNodeKind::Text("!".to_string()), span: Span::new(44, 45) },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_command_agent_and_toolchain() {
+        assert_eq!(
+            kinds("/file:src/main.rs#L1-L20\nAsk @reviewer with #kotlin\n/commit"),
+            vec![
+                BodyNodeKind::Command {
+                    name: "file".to_string(),
+                    argument: Some("src/main.rs#L1-L20".to_string()),
+                },
+                BodyNodeKind::Text("\nAsk ".to_string()),
+                BodyNodeKind::Agent("reviewer".to_string()),
+                BodyNodeKind::Text(" with ".to_string()),
+                BodyNodeKind::Toolchain("kotlin".to_string()),
+                BodyNodeKind::Text("\n".to_string()),
+                BodyNodeKind::Command { name: "commit".to_string(), argument: None },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keep_markdown_as_text() {
+        let input = "# Title\n\n| a | b |\n|---|---|\nmail me at a@b.com, it costs $5 or 1/2\n";
+        assert_eq!(kinds(input), vec![BodyNodeKind::Text(input.to_string())]);
+    }
+
+    #[test]
+    fn test_parse_code_block() {
+        assert_eq!(
+            kinds("Review:\n```java\nString a = \"$name\";\n```\nDone"),
+            vec![
+                BodyNodeKind::Text("Review:\n".to_string()),
+                BodyNodeKind::CodeBlock {
+                    language: "java".to_string(),
+                    code: "String a = \"$name\";".to_string(),
+                },
+                BodyNodeKind::Text("\nDone".to_string()),
+            ]
+        );
+        assert_eq!(
+            kinds("```\nunclosed $code"),
+            vec![BodyNodeKind::CodeBlock { language: "".to_string(), code: "unclosed $code".to_string() }]
+        );
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        let source = "[#]: # (explain (briefly))\nExplain $selection\n[//]: # (TODO)\n[link]: https://a.b\n";
+        let nodes = parse_body(source, 0);
+        assert_eq!(
+            nodes.iter().map(|node| node.kind.clone()).collect::<Vec<_>>(),
+            vec![
+                BodyNodeKind::Comment("explain (briefly)".to_string()),
+                BodyNodeKind::Text("Explain ".to_string()),
+                BodyNodeKind::Variable("selection".to_string()),
+                BodyNodeKind::Text("\n".to_string()),
+                BodyNodeKind::Comment("TODO".to_string()),
+                BodyNodeKind::Text("[link]: https://a.b\n".to_string()),
+            ]
+        );
+        assert_eq!(nodes[0].span, Span::new(0, 27));
+        assert_eq!(strip_comments(source, &nodes), "Explain $selection\n[link]: https://a.b\n");
+    }
+}
@@ -0,0 +1,284 @@
+use crate::runtime::Value;
+use std::cmp::Ordering;
+
+/// The operand coerced for comparison, both operands of comparison are coerced to the same kind
+#[derive(Debug, PartialEq)]
+enum Operand<'v> {
+    Null,
+    Bool(bool),
+    Number(f64),
+    /// year, month, day, hour, minute, second and nanosecond
+    Date([u32; 7]),
+    Version(Version),
+    Text(&'v str),
+}
+
+/// The semantic version, for example: `1.10.0`, `v2.0.0-beta.1` or `v3`, the build metadata after `+`
+/// is ignored.
+#[derive(Debug)]
+struct Version {
+    numbers: Vec<u64>,
+    pre_release: Vec<String>,
+}
+
+impl Version {
+    /// The version needs two numbers like `1.10` unless it starts with `v`, so `1` is still a number
+    fn parse(text: &str) -> Option<Version> {
+        let (text, prefixed) = match text.strip_prefix('v') {
+            Some(text) => (text, true),
+            None => (text, false),
+        };
+        let text = text.split_once('+').map_or(text, |(text, _)| text);
+        let (core, pre_release) = match text.split_once('-') {
+            Some((core, pre_release)) => (core, Some(pre_release)),
+            None => (text, None),
+        };
+
+        let numbers = core.split('.').map(|it| digits(it)?.parse().ok()).collect::<Option<Vec<u64>>>()?;
+        if numbers.len() < 2 && !prefixed {
+            return None;
+        }
+
+        let pre_release = match pre_release {
+            Some(pre_release) => {
+                let identifier = |it: &str| !it.is_empty() && it.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+                pre_release.split('.').map(|it| identifier(it).then(|| it.to_string())).collect::<Option<Vec<_>>>()?
+            }
+            None => vec![],
+        };
+        Some(Version { numbers, pre_release })
+    }
+}
+
+/// The numbers are compared in order where the missing ones are zero, then the release is greater than its
+/// pre-release, and the pre-release identifiers are compared as numbers or text.
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.numbers.len().max(other.numbers.len());
+        let number = |version: &Version, index: usize| version.numbers.get(index).copied().unwrap_or(0);
+        let numbers = (0..len).map(|index| number(self, index).cmp(&number(other, index))).find(|it| it.is_ne());
+
+        numbers.unwrap_or_else(|| match (self.pre_release.is_empty(), other.pre_release.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => {
+                let identifiers = self.pre_release.iter().zip(&other.pre_release).map(|(a, b)| {
+                    match (a.parse::<u64>(), b.parse::<u64>()) {
+                        (Ok(a), Ok(b)) => a.cmp(&b),
+                        (Ok(_), Err(_)) => Ordering::Less,
+                        (Err(_), Ok(_)) => Ordering::Greater,
+                        (Err(_), Err(_)) => a.cmp(b),
+                    }
+                });
+                let len = self.pre_release.len().cmp(&other.pre_release.len());
+                identifiers.chain([len]).find(|it| it.is_ne()).unwrap_or(Ordering::Equal)
+            }
+        })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Version {}
+
+fn digits(text: &str) -> Option<&str> {
+    Some(text).filter(|it| !it.is_empty() && it.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    text.parse::<f64>().ok().filter(|it| it.is_finite())
+}
+
+/// Parse the date like `2024-05-01`, `2024-05-01T10:30` or `2024-05-01 10:30:00.5Z`, the offset other than `Z`
+/// is not supported.
+fn parse_date(text: &str) -> Option<[u32; 7]> {
+    let text = text.strip_suffix('Z').unwrap_or(text);
+    let (date, time) = match text.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+
+    let mut parts = [0; 7];
+    let date: Vec<&str> = date.split('-').collect();
+    if date.len() != 3 || date.iter().map(|it| it.len()).ne([4, 2, 2]) {
+        return None;
+    }
+    for (index, part) in date.iter().enumerate() {
+        parts[index] = digits(part)?.parse().ok()?;
+    }
+
+    if let Some(time) = time {
+        let (clock, fraction) = match time.split_once('.') {
+            Some((clock, fraction)) => (clock, Some(fraction)),
+            None => (time, None),
+        };
+        let clock: Vec<&str> = clock.split(':').collect();
+        if !(2..=3).contains(&clock.len()) || clock.iter().any(|it| it.len() != 2) {
+            return None;
+        }
+        for (index, part) in clock.iter().enumerate() {
+            parts[3 + index] = digits(part)?.parse().ok()?;
+        }
+        if let Some(fraction) = fraction {
+            // the nanoseconds, the digits after the ninth are dropped
+            let fraction = digits(fraction)?;
+            parts[6] = format!("{:0<9}", &fraction[..fraction.len().min(9)]).parse().ok()?;
+        }
+    }
+
+    let date_valid = (1..=12).contains(&parts[1]) && (1..=31).contains(&parts[2]);
+    let time_valid = parts[3] < 24 && parts[4] < 60 && parts[5] <= 60;
+    (date_valid && time_valid).then_some(parts)
+}
+
+/// Coerce the texts for ordering, both are compared as dates, versions, numbers or text in order, so that
+/// `"1.10" > "1.9"` compares versions
+fn coerce_texts<'v>(left: &'v str, right: &'v str) -> (Operand<'v>, Operand<'v>) {
+    if let (Some(left), Some(right)) = (parse_date(left), parse_date(right)) {
+        return (Operand::Date(left), Operand::Date(right));
+    }
+    if let (Some(left), Some(right)) = (Version::parse(left), Version::parse(right)) {
+        return (Operand::Version(left), Operand::Version(right));
+    }
+    if let (Some(left), Some(right)) = (parse_number(left), parse_number(right)) {
+        return (Operand::Number(left), Operand::Number(right));
+    }
+    (Operand::Text(left), Operand::Text(right))
+}
+
+/// The text of string, file or symbol, which is compared as it is with another text
+fn text_of(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(value) | Value::File(value) | Value::Symbol(value) => Some(value),
+        _ => None,
+    }
+}
+
+fn parse_bool(text: &str) -> Option<bool> {
+    match text {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Coerce the operands to the same kind, the text is converted to the kind of the other operand, for example:
+/// `"120" > 100` compares numbers since the variables from host are text. Returns `None` if they can't be
+/// converted, for example: `"abc" > 100`.
+fn coerce<'v>(left: &'v Value, right: &'v Value) -> Option<(Operand<'v>, Operand<'v>)> {
+    let operands = match (left, right) {
+        (Value::Null, Value::Null) => (Operand::Null, Operand::Null),
+        (Value::Bool(left), Value::Bool(right)) => (Operand::Bool(*left), Operand::Bool(*right)),
+        (Value::Bool(left), right) => (Operand::Bool(*left), Operand::Bool(parse_bool(right.as_str()?)?)),
+        (left, Value::Bool(right)) => (Operand::Bool(parse_bool(left.as_str()?)?), Operand::Bool(*right)),
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            (Operand::Number(left.as_float()?), Operand::Number(right.as_float()?))
+        }
+        (Value::Int(_) | Value::Float(_), right) => {
+            (Operand::Number(left.as_float()?), Operand::Number(parse_number(right.as_str()?)?))
+        }
+        (left, Value::Int(_) | Value::Float(_)) => {
+            (Operand::Number(parse_number(left.as_str()?)?), Operand::Number(right.as_float()?))
+        }
+        (Value::Date(_), _) | (_, Value::Date(_)) => {
+            (Operand::Date(parse_date(left.as_str()?)?), Operand::Date(parse_date(right.as_str()?)?))
+        }
+        (left, right) => coerce_texts(left.as_str()?, right.as_str()?),
+    };
+    Some(operands)
+}
+
+/// Whether the values are equal for `==`, `!=`, `in` and the list methods `contains` and `indexOf`. Two texts
+/// are equal only if they are the same, so `"1.0" == "1"` is false, the text is coerced only for the other
+/// operand of number, bool or date like [compare], so `"1.0" == 1` is true. The values can't be coerced are equal
+/// only if they are the same, for example: the lists.
+pub fn equals(left: &Value, right: &Value) -> bool {
+    if let (Some(left), Some(right)) = (text_of(left), text_of(right)) {
+        return left == right;
+    }
+    match coerce(left, right) {
+        Some((left, right)) => left == right,
+        None => left == right,
+    }
+}
+
+/// Compare the values for `<`, `<=`, `>` and `>=`:
+///
+/// - numbers are compared by value, the text is parsed as number if the other is number
+/// - dates are compared by time, the text is parsed as date if the other is date
+/// - texts are compared as dates if both are dates, then as versions like `"1.10" > "1.9"`, as numbers like
+///   `"10" > "9"`, and by chars at last
+///
+/// it's an error if the values can't be coerced or have no order like bool, for example:
+/// `cannot compare string `abc` with int `100``.
+pub fn compare(left: &Value, right: &Value) -> Result<Ordering, String> {
+    let error = || format!("cannot compare {} with {}", left.describe(), right.describe());
+    match coerce(left, right).ok_or_else(error)? {
+        (Operand::Number(left), Operand::Number(right)) => left.partial_cmp(&right).ok_or_else(error),
+        (Operand::Date(left), Operand::Date(right)) => Ok(left.cmp(&right)),
+        (Operand::Version(left), Operand::Version(right)) => Ok(left.cmp(&right)),
+        (Operand::Text(left), Operand::Text(right)) => Ok(left.cmp(right)),
+        _ => Err(format!("{} values have no order", left.type_name())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: &str) -> Value {
+        Value::from(value)
+    }
+
+    #[test]
+    fn test_compare_numbers() {
+        assert_eq!(compare(&text("10"), &text("9")), Ok(Ordering::Greater));
+        assert_eq!(compare(&text("120"), &Value::Int(100)), Ok(Ordering::Greater));
+        assert_eq!(compare(&Value::Float(0.5), &Value::Int(1)), Ok(Ordering::Less));
+        assert!(equals(&text("1.0"), &Value::Int(1)));
+        assert!(!equals(&text("1.0"), &text("1")));
+        assert!(!equals(&text("007"), &text("7")));
+        assert!(!equals(&text(" 5"), &Value::Int(5)));
+        assert!(!equals(&text("1e3"), &text("1000")));
+        assert!(!equals(&text("abc"), &Value::Int(1)));
+        assert_eq!(compare(&text("abc"), &Value::Int(100)), Err("cannot compare string `abc` with int `100`".to_string()));
+    }
+
+    #[test]
+    fn test_compare_dates_and_versions() {
+        let date = Value::Date("2024-05-01".to_string());
+        assert_eq!(compare(&date, &text("2024-04-30T23:59:59Z")), Ok(Ordering::Greater));
+        assert_eq!(compare(&text("2024-05-01 10:30"), &text("2024-05-01T10:30:00.000")), Ok(Ordering::Equal));
+        assert_eq!(compare(&date, &text("May 1")), Err("cannot compare date `2024-05-01` with string `May 1`".to_string()));
+
+        assert_eq!(compare(&text("1.10.0"), &text("1.9.0")), Ok(Ordering::Greater));
+        assert_eq!(compare(&text("v2"), &text("v1.9.9")), Ok(Ordering::Greater));
+        assert_eq!(compare(&text("1.0.0-alpha.1"), &text("1.0.0-alpha.beta")), Ok(Ordering::Less));
+        assert_eq!(compare(&text("1.0.0-rc.1"), &text("1.0.0")), Ok(Ordering::Less));
+        assert_eq!(compare(&text("1.10"), &text("1.9")), Ok(Ordering::Greater));
+        assert_eq!(compare(&text("1.0.0+build.5"), &text("1.0.0")), Ok(Ordering::Equal));
+        assert!(!equals(&text("1.0.0+build.5"), &text("1.0.0")));
+        assert!(equals(&date, &text("2024-05-01T00:00")));
+    }
+
+    #[test]
+    fn test_compare_bools_and_others() {
+        assert!(equals(&text("true"), &Value::Bool(true)));
+        assert!(!equals(&Value::Null, &text("")));
+        assert!(equals(&Value::from(vec!["a"]), &Value::from(vec!["a"])));
+        assert_eq!(compare(&Value::Bool(true), &Value::Bool(false)), Err("bool values have no order".to_string()));
+        assert_eq!(compare(&text("b"), &text("a")), Ok(Ordering::Greater));
+        assert_eq!(compare(&Value::from(vec!["a"]), &Value::Int(1)), Err("cannot compare list with int `1`".to_string()));
+    }
+}
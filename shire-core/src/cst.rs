@@ -0,0 +1,393 @@
+use crate::body::{parse_body, BodyNodeKind};
+use crate::lexer::{tokenize_front_matter, Token, TokenKind};
+use crate::parser::parse_object_key;
+use crate::span::Span;
+use std::fmt;
+
+/// The kind of node or token in the syntax tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxKind {
+    /// The whole file, which is the front matter and the body
+    File,
+    /// The front matter between `---` lines, including the whitespaces around the delimiters
+    FrontMatter,
+    /// The `key: value` entry of front matter, the value may span multiple lines
+    Entry,
+    /// The value of entry, which is split into lines of text and trivia
+    Value,
+    /// The prompt after the front matter
+    Body,
+
+    Whitespace,
+    Newline,
+    /// The comment line, for example: `# TODO` in front matter or `[#]: # (TODO)` in body
+    Comment,
+    /// The `---` line of front matter
+    Delimiter,
+    Key,
+    Colon,
+    Text,
+    Variable,
+    Command,
+    Agent,
+    Toolchain,
+    CodeBlock,
+}
+
+impl SyntaxKind {
+    /// Trivia is the whitespace and comment, which has no effect on the AST
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, SyntaxKind::Whitespace | SyntaxKind::Newline | SyntaxKind::Comment)
+    }
+}
+
+/// The leaf of syntax tree, which owns the text of its span
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxToken {
+    pub kind: SyntaxKind,
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxElement {
+    pub fn kind(&self) -> SyntaxKind {
+        match self {
+            SyntaxElement::Node(node) => node.kind,
+            SyntaxElement::Token(token) => token.kind,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxElement::Node(node) => node.span,
+            SyntaxElement::Token(token) => token.span,
+        }
+    }
+}
+
+/// The node of lossless syntax tree, the children cover the span of node without gaps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxNode {
+    pub kind: SyntaxKind,
+    pub span: Span,
+    pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+    /// The node from `start` to the end of its last child
+    fn new(kind: SyntaxKind, children: Vec<SyntaxElement>, start: usize) -> Self {
+        let end = children.last().map(|it| it.span().end).unwrap_or(start);
+        SyntaxNode { kind, span: Span::new(start, end), children }
+    }
+
+    /// The first child node of `kind`
+    pub fn child(&self, kind: SyntaxKind) -> Option<&SyntaxNode> {
+        self.nodes(kind).next()
+    }
+
+    /// The child nodes of `kind`
+    pub fn nodes(&self, kind: SyntaxKind) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(move |it| match it {
+            SyntaxElement::Node(node) if node.kind == kind => Some(node),
+            _ => None,
+        })
+    }
+
+    /// All the tokens under this node in source order
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut tokens = vec![];
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => tokens.extend(node.tokens()),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+        tokens
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.tokens().iter().try_for_each(|token| f.write_str(&token.text))
+    }
+}
+
+fn token(source: &str, kind: SyntaxKind, start: usize, end: usize) -> SyntaxElement {
+    SyntaxElement::Token(SyntaxToken { kind, text: source[start..end].to_string(), span: Span::new(start, end) })
+}
+
+fn newline_len(input: &str) -> Option<usize> {
+    match input {
+        _ if input.starts_with('\n') => Some(1),
+        _ if input.starts_with("\r\n") => Some(2),
+        _ => None,
+    }
+}
+
+/// The length of spaces and tabs, `\r` is whitespace only if it's not a part of line break
+fn whitespace_len(input: &str) -> usize {
+    let mut length = 0;
+    for c in input.chars() {
+        match c {
+            ' ' | '\t' => length += 1,
+            '\r' if !input[length..].starts_with("\r\n") => length += 1,
+            _ => break,
+        }
+    }
+    length
+}
+
+/// The comment of lexer which starts at `index`, see [crate::lexer::tokenize_code] for where a comment starts
+fn comment_at(comments: &[Span], index: usize) -> Option<&Span> {
+    comments.binary_search_by_key(&index, |it| it.start).ok().map(|found| &comments[found])
+}
+
+/// Push the whitespaces, line breaks and `comments` from `start`, returns the end of trivia
+fn push_trivia(source: &str, start: usize, comments: &[Span], children: &mut Vec<SyntaxElement>) -> usize {
+    let mut index = start;
+    while index < source.len() {
+        let rest = &source[index..];
+        let (kind, length) = match newline_len(rest) {
+            Some(length) => (SyntaxKind::Newline, length),
+            None if whitespace_len(rest) > 0 => (SyntaxKind::Whitespace, whitespace_len(rest)),
+            None => match comment_at(comments, index) {
+                Some(comment) => (SyntaxKind::Comment, comment.len()),
+                None => break,
+            },
+        };
+
+        children.push(token(source, kind, index, index + length));
+        index += length;
+    }
+
+    index
+}
+
+/// The key starts a line, for example: `name: "Summary"`, the lines in multi-line strings are one token
+fn is_key(source: &str, token: &Token) -> bool {
+    let at_line_start = token.span.start == 0 || source[..token.span.start].ends_with('\n');
+    let after_key = parse_object_key(&source[token.span.start..]).map(|(rest, _)| rest.trim_start_matches([' ', '\t']));
+    at_line_start && after_key.is_ok_and(|rest| rest.starts_with(':'))
+}
+
+/// The end of entry from `start`, which is the end of its last token before the next key or `---`,
+/// so the blank lines and comments after the entry are left to the front matter.
+fn entry_end(source: &str, tokens: &[Token], start: usize) -> usize {
+    tokens
+        .iter()
+        .skip_while(|it| it.span.start < start)
+        .enumerate()
+        .take_while(|(index, it)| *index == 0 || !(it.kind == TokenKind::Delimiter || is_key(source, it)))
+        .filter(|(_, it)| !it.kind.is_trivia())
+        .map(|(_, it)| it.span.end)
+        .last()
+        .unwrap_or(start)
+}
+
+/// The value from `start` to `end` split into lines of text and trivia, the `comments` in the value are trivia
+fn parse_value(source: &str, start: usize, end: usize, comments: &[Span]) -> SyntaxNode {
+    let mut children = vec![];
+    let mut index = start;
+    while index < end {
+        let rest = &source[index..end];
+        let (kind, length) = match (newline_len(rest), comment_at(comments, index)) {
+            (Some(length), _) => (SyntaxKind::Newline, length),
+            _ if whitespace_len(rest) > 0 => (SyntaxKind::Whitespace, whitespace_len(rest)),
+            (None, Some(comment)) => (SyntaxKind::Comment, comment.len()),
+            (None, None) => {
+                // the text runs to the end of line or the comment after it
+                let line_end = rest.find('\n').unwrap_or(rest.len());
+                let text_end = comments
+                    .iter()
+                    .find(|it| it.start > index && it.start < index + line_end)
+                    .map_or(line_end, |it| it.start - index);
+                (SyntaxKind::Text, rest[..text_end].trim_end_matches([' ', '\t', '\r']).len())
+            }
+        };
+
+        children.push(token(source, kind, index, index + length));
+        index += length;
+    }
+
+    SyntaxNode::new(SyntaxKind::Value, children, start)
+}
+
+/// The entry from `start` to `end`, the text without `key:` prefix is kept as value
+fn parse_entry(source: &str, start: usize, end: usize, comments: &[Span]) -> SyntaxNode {
+    let mut children = vec![];
+    let text = &source[start..end];
+    let key_len = parse_object_key(text).map_or(0, |(rest, _)| text.len() - rest.len());
+    let spaces = whitespace_len(&text[key_len..]);
+
+    let mut index = start;
+    if key_len > 0 && text[key_len + spaces..].starts_with(':') {
+        children.push(token(source, SyntaxKind::Key, start, start + key_len));
+        if spaces > 0 {
+            children.push(token(source, SyntaxKind::Whitespace, start + key_len, start + key_len + spaces));
+        }
+
+        index = start + key_len + spaces;
+        children.push(token(source, SyntaxKind::Colon, index, index + 1));
+        index += 1;
+
+        let spaces = whitespace_len(&source[index..end]);
+        if spaces > 0 {
+            children.push(token(source, SyntaxKind::Whitespace, index, index + spaces));
+            index += spaces;
+        }
+    }
+
+    if index < end {
+        children.push(SyntaxElement::Node(parse_value(source, index, end, comments)));
+    }
+
+    SyntaxNode::new(SyntaxKind::Entry, children, start)
+}
+
+/// Parse the front matter from `start` of `source`, returns `None` if it doesn't start with `---`.
+/// The front matter without closing `---` ends at the end of source.
+pub(crate) fn parse_front_matter(source: &str, start: usize) -> Option<SyntaxNode> {
+    let mut children = vec![];
+    let mut index = push_trivia(source, start, &[], &mut children);
+    if !source[index..].starts_with("---") {
+        return None;
+    }
+
+    children.push(token(source, SyntaxKind::Delimiter, index, index + 3));
+    let tokens = tokenize_front_matter(source, index + 3);
+    let comments: Vec<Span> = tokens.iter().filter(|it| it.kind == TokenKind::Comment).map(|it| it.span).collect();
+    let close = tokens.iter().find(|it| it.kind == TokenKind::Delimiter).map(|it| it.span.start);
+
+    index = push_trivia(source, index + 3, &comments, &mut children);
+    while index < close.unwrap_or(source.len()) {
+        let end = entry_end(source, &tokens, index);
+        children.push(SyntaxElement::Node(parse_entry(source, index, end, &comments)));
+        index = push_trivia(source, end, &comments, &mut children);
+    }
+
+    if let Some(close) = close {
+        children.push(token(source, SyntaxKind::Delimiter, close, close + 3));
+        push_trivia(source, close + 3, &[], &mut children);
+    }
+
+    Some(SyntaxNode::new(SyntaxKind::FrontMatter, children, start))
+}
+
+/// Parse the body from `start` of `source`, each body node is a token
+fn parse_body_node(source: &str, start: usize) -> SyntaxNode {
+    let children = parse_body(&source[start..], start)
+        .into_iter()
+        .map(|node| {
+            let kind = match node.kind {
+                BodyNodeKind::Text(_) => SyntaxKind::Text,
+                BodyNodeKind::Variable(_) => SyntaxKind::Variable,
+                BodyNodeKind::Command { .. } => SyntaxKind::Command,
+                BodyNodeKind::Agent(_) => SyntaxKind::Agent,
+                BodyNodeKind::Toolchain(_) => SyntaxKind::Toolchain,
+                BodyNodeKind::CodeBlock { .. } => SyntaxKind::CodeBlock,
+                BodyNodeKind::Comment(_) => SyntaxKind::Comment,
+            };
+            token(source, kind, node.span.start, node.span.end)
+        })
+        .collect();
+
+    SyntaxNode::new(SyntaxKind::Body, children, start)
+}
+
+/// Parse the lossless syntax tree of Shire file, every byte of `source` is owned by a token,
+/// so `to_string()` of the tree is always `source`, even if the source is malformed.
+///
+/// The AST in [crate::parser::ShireFile] is derived from this tree.
+pub fn parse_cst(source: &str) -> SyntaxNode {
+    let front_matter = parse_front_matter(source, 0);
+    let body_start = front_matter.as_ref().map(|it| it.span.end).unwrap_or(0);
+
+    let mut children: Vec<SyntaxElement> = front_matter.into_iter().map(SyntaxElement::Node).collect();
+    children.push(SyntaxElement::Node(parse_body_node(source, body_start)));
+    SyntaxNode::new(SyntaxKind::File, children, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let sources = [
+            "",
+            "no front matter\n",
+            "\n---\r\nname: \"Summary\"  \r\n# TODO\r\n---\r\n\r\nExplain $selection\r\n",
+            "---\nvariables:\n  // the files\n  \"var1\": /.*\\.rs/ { sort }\n\n  \"doc\": \"\"\"\n# text\n\"\"\"\n---\n",
+            "---\nname \"missing colon\"\n  model:\n\t- ???\n",
+            "---\n---\n[#]: # (comment)\n/file:a.rs\n```\ncode\n",
+        ];
+
+        for source in sources {
+            assert_eq!(parse_cst(source).to_string(), source);
+        }
+    }
+
+    #[test]
+    fn test_front_matter_structure() {
+        let source = "---\n# TODO\nname: \"Summary\"\nvariables:\n  \"doc\": \"\"\"\n# text\n\"\"\"\n  # end\n---\nBody\n";
+        let tree = parse_cst(source);
+        let front_matter = tree.child(SyntaxKind::FrontMatter).unwrap();
+
+        let keys: Vec<&str> = front_matter
+            .nodes(SyntaxKind::Entry)
+            .filter_map(|entry| entry.children.first())
+            .map(|key| match key {
+                SyntaxElement::Token(token) => token.text.as_str(),
+                SyntaxElement::Node(_) => "",
+            })
+            .collect();
+        assert_eq!(keys, vec!["name", "variables"]);
+
+        let comments: Vec<&str> = front_matter
+            .tokens()
+            .into_iter()
+            .filter(|token| token.kind == SyntaxKind::Comment)
+            .map(|token| token.text.as_str())
+            .collect();
+        assert_eq!(comments, vec!["# TODO", "# end"]);
+
+        let body = tree.child(SyntaxKind::Body).unwrap();
+        assert_eq!(body.span, Span::new(source.len() - 5, source.len()));
+    }
+
+    #[test]
+    fn test_comments_in_entries() {
+        let source = "---\nname: \"C# a\" # trailing\nvariables:\n  \"v\": /x/ {\n    # inside\n    sort // why\n  }\n---\n";
+        let tree = parse_cst(source);
+        assert_eq!(tree.to_string(), source);
+
+        let front_matter = tree.child(SyntaxKind::FrontMatter).unwrap();
+        let entries: Vec<&str> =
+            front_matter.nodes(SyntaxKind::Entry).map(|entry| &source[entry.span.start..entry.span.end]).collect();
+        assert_eq!(entries, vec!["name: \"C# a\"", "variables:\n  \"v\": /x/ {\n    # inside\n    sort // why\n  }"]);
+
+        let value = front_matter.nodes(SyntaxKind::Entry).nth(1).unwrap().child(SyntaxKind::Value).unwrap();
+        let texts: Vec<(SyntaxKind, &str)> = value
+            .tokens()
+            .into_iter()
+            .filter(|token| !matches!(token.kind, SyntaxKind::Whitespace | SyntaxKind::Newline))
+            .map(|token| (token.kind, token.text.as_str()))
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                (SyntaxKind::Text, "\"v\": /x/ {"),
+                (SyntaxKind::Comment, "# inside"),
+                (SyntaxKind::Text, "sort"),
+                (SyntaxKind::Comment, "// why"),
+                (SyntaxKind::Text, "}"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use crate::span::Span;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// The stable code of diagnostic, which can be used to look up or suppress the diagnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticCode {
+    /// The input does not match the grammar of Shire
+    SyntaxError,
+    /// The front matter key is not supported, for example: `nmae: "Summary"`
+    UnknownKey,
+    /// The value does not match the grammar of its front matter key
+    InvalidValue,
+    /// The interaction is not one of `InteractionType`, for example: `interaction: AppendCurson`
+    UnknownInteraction,
+    /// The action location is not one of `ShireActionLocation`
+    UnknownActionLocation,
+    /// The arguments do not match the pipeline function, for example: `head("ten")`
+    InvalidArgument,
+    /// The number literal does not fit in 64 bits, for example: `limit: 99999999999999999999`
+    NumberOverflow,
+    /// The regex literal does not compile or has unknown flags, for example: `/(/` or `/error/g`
+    InvalidRegex,
+    /// The key is defined more than once, the later definition overrides the earlier one
+    DuplicateKey,
+}
+
+impl DiagnosticCode {
+    pub fn code(&self) -> &'static str {
+        match self {
+            DiagnosticCode::SyntaxError => "SH000",
+            DiagnosticCode::UnknownKey => "SH001",
+            DiagnosticCode::InvalidValue => "SH002",
+            DiagnosticCode::UnknownInteraction => "SH003",
+            DiagnosticCode::UnknownActionLocation => "SH004",
+            DiagnosticCode::InvalidArgument => "SH005",
+            DiagnosticCode::NumberOverflow => "SH006",
+            DiagnosticCode::InvalidRegex => "SH007",
+            DiagnosticCode::DuplicateKey => "SH008",
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: DiagnosticCode,
+    pub message: String,
+    pub span: Span,
+    /// 1-based line where the span starts
+    pub line: usize,
+    /// 1-based column (counted in chars) where the span starts
+    pub column: usize,
+    /// The secondary locations, for example: the first definition of duplicate key
+    pub related: Vec<RelatedSpan>,
+}
+
+/// The secondary location of diagnostic with a short note, for example: `first defined here`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelatedSpan {
+    pub message: String,
+    pub span: Span,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: DiagnosticCode, message: String, span: Span, source: &str) -> Self {
+        let (line, column) = span.line_column(source);
+        Diagnostic { severity, code, message, span, line, column, related: vec![] }
+    }
+
+    pub fn with_related(mut self, message: String, span: Span, source: &str) -> Self {
+        let (line, column) = span.line_column(source);
+        self.related.push(RelatedSpan { message, span, line, column });
+        self
+    }
+
+    /// Render the diagnostic with the source line and a caret under the span, for example:
+    ///
+    /// ```text
+    /// warning[SH001]: unknown key `nmae`
+    ///  --> 2:1
+    ///   |
+    /// 2 | nmae: "Summary"
+    ///   | ^^^^
+    /// ```
+    ///
+    /// the related spans follow with their notes, and are underlined with `-`.
+    pub fn render(&self, source: &str) -> String {
+        let width = self.related.iter().map(|it| it.line).chain([self.line]).max().unwrap_or(1).to_string().len();
+        let padding = " ".repeat(width);
+
+        let mut rendered = format!(
+            "{}[{}]: {}\n{}--> {}:{}\n{} |\n{}",
+            self.severity, self.code, self.message,
+            padding, self.line, self.column,
+            padding,
+            snippet(source, self.span, self.line, self.column, '^', width)
+        );
+        for related in &self.related {
+            let snippet = snippet(source, related.span, related.line, related.column, '-', width);
+            rendered.push_str(&format!("\n{} |\n{} {}", padding, snippet, related.message));
+        }
+        rendered
+    }
+}
+
+/// The source line of `span` and the marks under it, the line number is padded to `width`
+fn snippet(source: &str, span: Span, line: usize, column: usize, mark: char, width: usize) -> String {
+    let start = span.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map(|index| index + 1).unwrap_or(0);
+    let line_end = source[start..].find('\n').map(|index| start + index).unwrap_or(source.len());
+    let text = source[line_start..line_end].trim_end_matches('\r');
+
+    let end = span.end.clamp(start, line_end);
+    let mark_width = source[start..end].chars().count().max(1);
+    let marks = format!("{}{}", " ".repeat(column - 1), mark.to_string().repeat(mark_width));
+
+    format!("{:>width$} | {}\n{} | {}", line, text, " ".repeat(width), marks, width = width)
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}] {}:{}: {}", self.severity, self.code, self.line, self.column, self.message)
+    }
+}
+
+/// Collects the diagnostics while parsing, spans are computed from the slices of `source`.
+pub(crate) struct Diagnostics<'a> {
+    source: &'a str,
+    diagnostics: Vec<Diagnostic>,
+    /// In recovering mode, the parser skips the malformed part instead of failing
+    recovering: bool,
+}
+
+impl<'a> Diagnostics<'a> {
+    pub(crate) fn new(source: &'a str) -> Self {
+        Diagnostics { source, diagnostics: vec![], recovering: false }
+    }
+
+    pub(crate) fn recovering(source: &'a str) -> Self {
+        Diagnostics { source, diagnostics: vec![], recovering: true }
+    }
+
+    pub(crate) fn is_recovering(&self) -> bool {
+        self.recovering
+    }
+
+    /// The span of `slice`, which must be a sub-slice of the source.
+    pub(crate) fn span_of(&self, slice: &str) -> Span {
+        Span::of(self.source, slice)
+    }
+
+    pub(crate) fn source(&self) -> &'a str {
+        self.source
+    }
+
+    pub(crate) fn report(&mut self, severity: Severity, code: DiagnosticCode, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic::new(severity, code, message, span, self.source));
+    }
+
+    /// Report the diagnostic with a related span, for example: the first definition of duplicate key
+    pub(crate) fn report_related(
+        &mut self,
+        severity: Severity,
+        code: DiagnosticCode,
+        message: String,
+        span: Span,
+        related: (String, Span),
+    ) {
+        let diagnostic = Diagnostic::new(severity, code, message, span, self.source);
+        self.diagnostics.push(diagnostic.with_related(related.0, related.1, self.source));
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub(crate) fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|it| it.severity == Severity::Error)
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<Diagnostic> {
+        self.diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_diagnostic() {
+        let source = "---\nnmae: \"Summary\"\n---\n";
+        let diagnostic = Diagnostic::new(
+            Severity::Warning,
+            DiagnosticCode::UnknownKey,
+            "unknown key `nmae`".to_string(),
+            Span::new(4, 8),
+            source,
+        );
+
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 1));
+        assert_eq!(
+            diagnostic.render(source),
+            "warning[SH001]: unknown key `nmae`\n --> 2:1\n  |\n2 | nmae: \"Summary\"\n  | ^^^^"
+        );
+    }
+
+    #[test]
+    fn test_render_related_span() {
+        let source = "variables:\n  \"var1\": \"demo\"\n  \"var1\": 42\n";
+        let diagnostic = Diagnostic::new(
+            Severity::Warning,
+            DiagnosticCode::DuplicateKey,
+            "duplicate variable `var1`".to_string(),
+            Span::new(30, 36),
+            source,
+        )
+        .with_related("first defined here".to_string(), Span::new(13, 19), source);
+
+        assert_eq!(
+            diagnostic.render(source),
+            "warning[SH008]: duplicate variable `var1`\n --> 3:3\n  |\n3 |   \"var1\": 42\n  |   ^^^^^^\n  |\n2 |   \"var1\": \"demo\"\n  |   ------ first defined here"
+        );
+    }
+
+    #[test]
+    fn test_span_of_sub_slice() {
+        let source = "name: \"Summary\"";
+        let diagnostics = Diagnostics::new(source);
+        assert_eq!(diagnostics.span_of(&source[6..]), Span::new(6, 15));
+    }
+}
@@ -0,0 +1,458 @@
+use crate::ast::front_matter_type::FrontMatterType;
+use crate::ast::shire_expression::{
+    BinaryExpression, Comparison, ConditionalExpression, LogicalExpression, MethodCall, NotExpression, Operator,
+    OperatorType, StatementType, Value,
+};
+use crate::diagnostic::{Diagnostic, DiagnosticCode, Severity};
+use crate::parser::{literal_error, parse_number, parse_quoted_string};
+use crate::span::Span;
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while, take_while1},
+    character::complete::{char, multispace0, satisfy},
+    combinator::{map, map_res, not, opt, recognize},
+    multi::separated_list0,
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult,
+};
+
+fn parse_identifier(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        take_while1(|c: char| c.is_alphabetic() || c == '_'),
+        take_while(|c: char| c.is_alphanumeric() || c == '_'),
+    ))(input)
+}
+
+/// The span from `start` to `rest`, both are the slices of `source`
+fn span_between(source: &str, start: &str, rest: &str) -> Span {
+    Span::new(Span::of(source, start).start, Span::of(source, rest).start)
+}
+
+/// The operand stored in comparison or method call, values are unwrapped and others are kept as expression
+pub(crate) fn into_operand(statement: StatementType) -> FrontMatterType {
+    match statement {
+        StatementType::Value(value) => value.value().clone(),
+        statement => FrontMatterType::EXPRESSION(statement),
+    }
+}
+
+/// Parser for literals, variables, identifiers, lists and parenthesized expressions
+/// for example: `"Foo"`, `42`, `true`, `null`, `$fileName`, `clazz`, `["kotlin", "java"]` or `($a || $b)`
+fn parse_primary<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, StatementType> {
+    if input.starts_with('(') {
+        return delimited(
+            pair(char('('), multispace0),
+            |input| parse_conditional(source, input),
+            pair(multispace0, char(')')),
+        )(input);
+    }
+
+    if input.starts_with('[') {
+        let (rest, elements) = delimited(
+            pair(char('['), multispace0),
+            separated_list0(tuple((multispace0, char(','), multispace0)), |input| parse_conditional(source, input)),
+            pair(multispace0, char(']')),
+        )(input)?;
+        let list = FrontMatterType::ARRAY(elements.into_iter().map(into_operand).collect());
+        return Ok((rest, StatementType::Value(Value::new(list)).with_span(span_between(source, input, rest))));
+    }
+
+    let (rest, value) = alt((
+        map(parse_quoted_string, FrontMatterType::STRING),
+        parse_number,
+        map(preceded(char('$'), parse_identifier), |name| FrontMatterType::VARIABLE(name.to_string())),
+        map(parse_identifier, |name| match name {
+            "true" => FrontMatterType::BOOLEAN(true),
+            "false" => FrontMatterType::BOOLEAN(false),
+            "null" => FrontMatterType::EMPTY,
+            _ => FrontMatterType::IDENTIFIER(name.to_string()),
+        }),
+    ))(input)?;
+
+    Ok((rest, StatementType::Value(Value::new(value)).with_span(span_between(source, input, rest))))
+}
+
+/// Parser for the chain of method calls and properties
+/// for example: `$filePath.lowercase().endsWith(".kt")` or `clazz.name`
+fn parse_postfix<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, StatementType> {
+    let (mut rest, mut expression) = parse_primary(source, input)?;
+
+    loop {
+        let arguments = delimited(
+            pair(char('('), multispace0),
+            separated_list0(tuple((multispace0, char(','), multispace0)), |input| parse_conditional(source, input)),
+            pair(multispace0, char(')')),
+        );
+        let Ok((after, (method, arguments))) = pair(preceded(char('.'), parse_identifier), opt(arguments))(rest) else {
+            return Ok((rest, expression));
+        };
+
+        let arguments = arguments.map(|arguments| arguments.into_iter().map(into_operand).collect());
+        let call = MethodCall::new(into_operand(expression), FrontMatterType::IDENTIFIER(method.to_string()), arguments);
+        expression = StatementType::MethodCall(call).with_span(span_between(source, input, after));
+        rest = after;
+    }
+}
+
+fn parse_unary<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, StatementType> {
+    match input.strip_prefix('!') {
+        Some(rest) => {
+            let (rest, operand) = parse_unary(source, rest.trim_start())?;
+            let not = StatementType::NotExpression(NotExpression::new(operand));
+            Ok((rest, not.with_span(span_between(source, input, rest))))
+        }
+        None => parse_postfix(source, input),
+    }
+}
+
+fn parse_binary_operator(input: &str) -> IResult<&str, OperatorType> {
+    map_res(
+        alt((
+            tag("||"),
+            tag("&&"),
+            tag("=="),
+            tag("!="),
+            tag("<="),
+            tag(">="),
+            tag("<"),
+            tag(">"),
+            tag("??"),
+            tag("+"),
+            // `---` closes the front matter
+            terminated(tag("-"), not(char('-'))),
+            tag("*"),
+            tag("/"),
+            tag("%"),
+            terminated(tag("in"), not(satisfy(|c| c.is_alphanumeric() || c == '_'))),
+        )),
+        OperatorType::from_str,
+    )(input)
+}
+
+/// Precedence climbing for binary operators, only the operators bind at least `min_precedence` are taken,
+/// so that `$a || $b && $c` is parsed as `$a || ($b && $c)`.
+fn parse_binary<'a>(source: &'a str, input: &'a str, min_precedence: u8) -> IResult<&'a str, StatementType> {
+    let (mut rest, mut left) = parse_unary(source, input)?;
+
+    while let Ok((after, operator)) = delimited(multispace0, parse_binary_operator, multispace0)(rest) {
+        let precedence = operator.precedence();
+        if precedence < min_precedence {
+            break;
+        }
+
+        let (after, right) = parse_binary(source, after, precedence + 1)?;
+        let statement = match operator {
+            OperatorType::And | OperatorType::Or => {
+                StatementType::LogicalExpression(LogicalExpression::new(left, operator, right))
+            }
+            OperatorType::Add
+            | OperatorType::Subtract
+            | OperatorType::Multiply
+            | OperatorType::Divide
+            | OperatorType::Modulo
+            | OperatorType::Coalesce => {
+                StatementType::BinaryExpression(BinaryExpression::new(into_operand(left), operator, into_operand(right)))
+            }
+            _ => StatementType::Comparison(Comparison::new(into_operand(left), Operator::new(operator), into_operand(right))),
+        };
+
+        left = statement.with_span(span_between(source, input, after));
+        rest = after;
+    }
+
+    Ok((rest, left))
+}
+
+/// Parser for the ternary expression, which is right associative,
+/// for example: `$size > 1000 ? "large" : $size > 100 ? "medium" : "small"`
+fn parse_conditional<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, StatementType> {
+    let (rest, condition) = parse_binary(source, input, 0)?;
+    let question: IResult<&str, char> = delimited(multispace0, char('?'), multispace0)(rest);
+    let Ok((after, _)) = question else {
+        return Ok((rest, condition));
+    };
+
+    let (after, then) = parse_conditional(source, after)?;
+    let (after, _) = delimited(multispace0, char(':'), multispace0)(after)?;
+    let (after, otherwise) = parse_conditional(source, after)?;
+
+    let conditional = ConditionalExpression::new(condition, into_operand(then), into_operand(otherwise));
+    Ok((after, StatementType::ConditionalExpression(conditional).with_span(span_between(source, input, after))))
+}
+
+/// Parser for the expression in `source`, the spans of the statements are the positions in `source`.
+pub(crate) fn expression<'a>(source: &'a str) -> impl Fn(&'a str) -> IResult<&'a str, StatementType> {
+    move |input| parse_conditional(source, input)
+}
+
+/// Parse the whole `source` as an expression, for example: `$filePath.lowercase().endsWith(".kt") && !$isTest`,
+/// precedence from high to low is `!`, `* / %`, `+ -`, `??`, comparison and `in`, `&&`, `||` and `?:`.
+pub fn parse_expression(source: &str) -> Result<StatementType, Diagnostic> {
+    let result = delimited(multispace0, expression(source), multispace0)(source);
+    if let Some((code, message, literal)) = result.as_ref().err().and_then(literal_error) {
+        let span = Span::of(source, literal);
+        return Err(Diagnostic::new(Severity::Error, code, message, span, source));
+    }
+
+    let remaining = match result {
+        Ok(("", statement)) => return Ok(statement),
+        Ok((remaining, _)) => remaining,
+        Err(nom::Err::Error(error) | nom::Err::Failure(error)) => error.input,
+        Err(nom::Err::Incomplete(_)) => &source[source.len()..],
+    };
+
+    let token = remaining.split_whitespace().next().unwrap_or_default();
+    let message = match token.is_empty() {
+        true => "unexpected end of expression".to_string(),
+        false => format!("unexpected `{}`", token),
+    };
+    let start = Span::of(source, remaining.trim_start()).start;
+    Err(Diagnostic::new(Severity::Error, DiagnosticCode::SyntaxError, message, Span::new(start, start + token.len()), source))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::shire_expression::Statement;
+    use crate::methods::MethodRegistry;
+    use crate::runtime::{Environment, Host, Value as RuntimeValue};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_method_chain() {
+        let source = r#"$filePath.lowercase().endsWith(".kt")"#;
+        let lowercase = MethodCall::new(
+            FrontMatterType::VARIABLE("filePath".to_string()),
+            FrontMatterType::IDENTIFIER("lowercase".to_string()),
+            Some(vec![]),
+        );
+
+        assert_eq!(
+            parse_expression(source),
+            Ok(StatementType::MethodCall(MethodCall::new(
+                FrontMatterType::EXPRESSION(StatementType::MethodCall(lowercase)),
+                FrontMatterType::IDENTIFIER("endsWith".to_string()),
+                Some(vec![FrontMatterType::STRING(".kt".to_string())]),
+            )))
+        );
+    }
+
+    #[test]
+    fn test_parse_precedence_and_parentheses() {
+        let statement = parse_expression(r#"!$a == false && ($b > 10 || $c != "x")"#).unwrap();
+        let expected = StatementType::LogicalExpression(LogicalExpression::new(
+            StatementType::Comparison(Comparison::new(
+                FrontMatterType::EXPRESSION(StatementType::NotExpression(NotExpression::new(StatementType::Value(
+                    Value::new(FrontMatterType::VARIABLE("a".to_string())),
+                )))),
+                Operator::new(OperatorType::Equal),
+                FrontMatterType::BOOLEAN(false),
+            )),
+            OperatorType::And,
+            StatementType::LogicalExpression(LogicalExpression::new(
+                StatementType::Comparison(Comparison::new(
+                    FrontMatterType::VARIABLE("b".to_string()),
+                    Operator::new(OperatorType::GreaterThan),
+                    FrontMatterType::NUMBER(10),
+                )),
+                OperatorType::Or,
+                StatementType::Comparison(Comparison::new(
+                    FrontMatterType::VARIABLE("c".to_string()),
+                    Operator::new(OperatorType::NotEqual),
+                    FrontMatterType::STRING("x".to_string()),
+                )),
+            )),
+        ));
+
+        assert_eq!(statement, expected);
+    }
+
+    #[test]
+    fn test_expression_spans() {
+        let source = r#"$a.trim() == "x" || !$b"#;
+        let statement = parse_expression(source).unwrap();
+        assert_eq!(statement.span(), Span::new(0, source.len()));
+
+        match statement {
+            StatementType::LogicalExpression(_) => {}
+            other => panic!("unexpected statement {:?}", other),
+        }
+
+        let not = parse_expression(&source[20..]).unwrap();
+        assert_eq!(not.span(), Span::new(0, 3));
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let sources = [
+            r#"$filePath.lowercase().endsWith(".kt")"#,
+            r#"!$a == false && ($b > 10 || $c != "x")"#,
+            r#"$a || $b && $c"#,
+            r#"($a || $b) && $c"#,
+            r#"$a && ($b && $c)"#,
+            r#"!($a == "x") || clazz.getName() == "Foo""#,
+            r#"$selection.substring(0, -1).contains($name)"#,
+            r#"$a == "say \"hi\"\n" || $b.endsWith("\\")"#,
+            r#"$a + $b * 2 - ($c - 1) % 3 >= 10 && $lang in ["kotlin", "java"]"#,
+            r#"$size > 100 ? "large" : $size > 10 ? "medium" : "small""#,
+            r#"($a ? $b : $c) ? $d : $name ?? "unknown" + ".kt""#,
+            r#"!($a + 1 > 2) == $b ?? false"#,
+            r#"$a == null || [null, $b] != $c ?? null"#,
+        ];
+
+        for source in sources {
+            let statement = parse_expression(source).unwrap();
+            assert_eq!(statement.display(), source);
+            assert_eq!(parse_expression(&statement.display()).unwrap(), statement);
+        }
+    }
+
+    #[test]
+    fn test_parse_numbers() {
+        let statement = parse_expression("$score >= -0.5 && $size < 4294967296").unwrap();
+        let expected = StatementType::LogicalExpression(LogicalExpression::new(
+            StatementType::Comparison(Comparison::new(
+                FrontMatterType::VARIABLE("score".to_string()),
+                Operator::new(OperatorType::GreaterEqual),
+                FrontMatterType::FLOAT(-0.5),
+            )),
+            OperatorType::And,
+            StatementType::Comparison(Comparison::new(
+                FrontMatterType::VARIABLE("size".to_string()),
+                Operator::new(OperatorType::LessThan),
+                FrontMatterType::NUMBER(4294967296),
+            )),
+        ));
+        assert_eq!(statement, expected);
+        assert_eq!(parse_expression(&statement.display()).unwrap(), statement);
+
+        let error = parse_expression("$size > 99999999999999999999").unwrap_err();
+        assert_eq!(error.code, DiagnosticCode::NumberOverflow);
+        assert_eq!(error.message, "number `99999999999999999999` is out of range");
+        assert_eq!(error.span, Span::new(8, 28));
+    }
+
+    #[test]
+    fn test_parse_arithmetic_precedence() {
+        let statement = parse_expression(r#"$a ?? 1 + $b * 2 > 3 ? "x" : "y""#).unwrap();
+        let sum = BinaryExpression::new(
+            FrontMatterType::NUMBER(1),
+            OperatorType::Add,
+            FrontMatterType::EXPRESSION(StatementType::BinaryExpression(BinaryExpression::new(
+                FrontMatterType::VARIABLE("b".to_string()),
+                OperatorType::Multiply,
+                FrontMatterType::NUMBER(2),
+            ))),
+        );
+        let coalesce = BinaryExpression::new(
+            FrontMatterType::VARIABLE("a".to_string()),
+            OperatorType::Coalesce,
+            FrontMatterType::EXPRESSION(StatementType::BinaryExpression(sum)),
+        );
+        let expected = StatementType::ConditionalExpression(ConditionalExpression::new(
+            StatementType::Comparison(Comparison::new(
+                FrontMatterType::EXPRESSION(StatementType::BinaryExpression(coalesce)),
+                Operator::new(OperatorType::GreaterThan),
+                FrontMatterType::NUMBER(3),
+            )),
+            FrontMatterType::STRING("x".to_string()),
+            FrontMatterType::STRING("y".to_string()),
+        ));
+
+        assert_eq!(statement, expected);
+        assert_eq!(parse_expression("$index inside").unwrap_err().message, "unexpected `inside`");
+    }
+
+    #[test]
+    fn test_report_unexpected_token() {
+        let error = parse_expression(r#"$a == "x" )"#).unwrap_err();
+        assert_eq!(error.message, "unexpected `)`");
+        assert_eq!(error.span, Span::new(10, 11));
+
+        let error = parse_expression("$a &&").unwrap_err();
+        assert_eq!(error.message, "unexpected end of expression");
+    }
+
+    #[test]
+    fn test_evaluate_typed_values() {
+        let env = Environment::from(HashMap::from([
+            ("filePath".to_string(), "src/Main.KT".to_string()),
+            ("name".to_string(), "main".to_string()),
+        ]));
+        let evaluate = |source: &str| parse_expression(source).unwrap().evaluate(&env);
+
+        assert_eq!(evaluate(r#"$filePath.lowercase().endsWith(".kt") && !($name == "test")"#), Ok(RuntimeValue::Bool(true)));
+        assert_eq!(evaluate("$name.length() >= 4.0"), Ok(RuntimeValue::Bool(true)));
+        assert_eq!(evaluate("$name.uppercase()"), Ok(RuntimeValue::from("MAIN")));
+        assert_eq!(evaluate(r#"$name && $name == "main""#), Err("left operand expected bool, found string".to_string()));
+
+        // the right operand is not evaluated if the left one decides the result
+        assert_eq!(evaluate(r#"$name == "test" && $name.foo()"#), Ok(RuntimeValue::Bool(false)));
+        assert_eq!(evaluate(r#"$name == "main" || $name.foo()"#), Ok(RuntimeValue::Bool(true)));
+        assert_eq!(evaluate(r#"$name == "main" && $name.foo()"#), Err("unknown method `foo` for string".to_string()));
+    }
+
+    #[test]
+    fn test_evaluate_typed_comparison() {
+        let env = Environment::from(HashMap::from([
+            ("lineCount".to_string(), "120".to_string()),
+            ("version".to_string(), "1.10.2".to_string()),
+            ("isTest".to_string(), "false".to_string()),
+        ]));
+        let evaluate = |source: &str| parse_expression(source).unwrap().evaluate(&env);
+
+        assert_eq!(evaluate(r#"$lineCount > 100 && $lineCount < 1e3"#), Ok(RuntimeValue::Bool(true)));
+        assert_eq!(evaluate(r#"$version >= "1.9.0" && $isTest == false"#), Ok(RuntimeValue::Bool(true)));
+        assert_eq!(evaluate(r#""10" < "9""#), Ok(RuntimeValue::Bool(false)));
+        assert_eq!(evaluate(r#"$version > 1"#), Err("cannot compare string `1.10.2` with int `1`".to_string()));
+    }
+
+    #[test]
+    fn test_evaluate_arithmetic_and_operators() {
+        let env = Environment::from(HashMap::from([
+            ("lineCount".to_string(), "120".to_string()),
+            ("lang".to_string(), "kotlin".to_string()),
+        ]));
+        let evaluate = |source: &str| parse_expression(source).unwrap().evaluate(&env);
+
+        assert_eq!(evaluate("$lineCount * 2 + 7 / 2 - 10 % 4"), Ok(RuntimeValue::Int(241)));
+        assert_eq!(evaluate("$lineCount / 2.0 > 59.5"), Ok(RuntimeValue::Bool(true)));
+        assert_eq!(evaluate(r#"$lang + "-" + $lineCount"#), Ok(RuntimeValue::from("kotlin-120")));
+        assert_eq!(evaluate(r#"$missing ?? $lang ?? "java""#), Ok(RuntimeValue::from("kotlin")));
+        assert_eq!(evaluate(r#"$missing == null && $lang != null"#), Ok(RuntimeValue::Bool(true)));
+        assert_eq!(evaluate("$missing ?? null"), Ok(RuntimeValue::Null));
+        assert_eq!(evaluate(r#"$lang in ["kotlin", "java"] && !("rust" in $lang)"#), Ok(RuntimeValue::Bool(true)));
+        let coerced = r#"1 in ["1.0"] && ["1.0"].contains(1) && !("1" in ["1.0"]) && "1" != "1.0""#;
+        assert_eq!(evaluate(coerced), Ok(RuntimeValue::Bool(true)));
+        assert_eq!(evaluate(r#"$lineCount > 100 ? "large" : $missing.foo()"#), Ok(RuntimeValue::from("large")));
+        assert_eq!(evaluate("$lineCount / (1 - 1)"), Err("division by zero".to_string()));
+        assert_eq!(evaluate("$lang - 1"), Err("cannot apply `-` to string `kotlin` and int `1`".to_string()));
+        assert_eq!(evaluate("$lang ? 1 : 2"), Err("condition of `?:` expected bool, found string".to_string()));
+    }
+
+    #[test]
+    fn test_evaluate_structured_variables() {
+        let host = HashMap::from([("file".to_string(), RuntimeValue::File("src/Main.kt".to_string()))]);
+        let mut env = Environment::with_host(&host as &dyn Host);
+        env.define("config", RuntimeValue::Object([("lang".to_string(), RuntimeValue::from("kotlin"))].into()));
+
+        let evaluate = |source: &str| parse_expression(source).unwrap().evaluate(&env);
+        assert_eq!(evaluate(r#"$file.name == "Main.kt" && $config.lang == "kotlin""#), Ok(RuntimeValue::Bool(true)));
+        assert_eq!(evaluate(r#"$file.extension.uppercase()"#), Ok(RuntimeValue::from("KT")));
+        assert_eq!(evaluate(r#"$missing.isEmpty()"#), Ok(RuntimeValue::Bool(true)));
+    }
+
+    #[test]
+    fn test_evaluate_method_library() {
+        let mut methods = MethodRegistry::standard();
+        methods.register("isKotlin", |call| Ok(RuntimeValue::Bool(call.text()?.ends_with(".kt"))));
+        let env = Environment::from(HashMap::from([("fileName".to_string(), "src/Main.kt".to_string())]))
+            .with_methods(&methods);
+
+        let evaluate = |source: &str| parse_expression(source).unwrap().evaluate(&env);
+        assert_eq!(evaluate(r#"$fileName.split("/").last().substring(0, -3)"#), Ok(RuntimeValue::from("Main")));
+        assert_eq!(evaluate(r#"$fileName.isKotlin() && $fileName.indexOf("/") == 3"#), Ok(RuntimeValue::Bool(true)));
+        assert_eq!(evaluate(r#"$fileName.first().first().foo()"#), Err("unknown method `foo` for string".to_string()));
+        assert_eq!(evaluate(r#"$fileName.contains()"#), Err("`contains` expects 1 argument, found 0".to_string()));
+    }
+}
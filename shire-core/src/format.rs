@@ -0,0 +1,622 @@
+use crate::ast::front_matter_type::{quote_string, FrontMatterType, ShirePsiQueryStatement};
+use crate::ast::pattern_action_fun::FunctionArg;
+use crate::ast::shire_expression::Statement;
+use crate::body::{BodyNode, BodyNodeKind};
+use crate::cst::{parse_cst, SyntaxElement, SyntaxKind, SyntaxNode};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{tokenize_front_matter, TokenKind};
+use crate::parser::{
+    parse, parse_object_key, parse_quoted_string, Function, HobbitHole, LifecycleHook, ShireFile, VariableTransform,
+};
+use crate::span::Span;
+use indexmap::IndexMap;
+
+/// The pipeline longer than this is broken into lines, one function per line
+const MAX_WIDTH: usize = 80;
+
+/// The keys of front matter in the order of [format_file], the other keys are user data printed after them
+const KEYS: [&str; 11] = [
+    "name",
+    "description",
+    "interaction",
+    "actionLocation",
+    "model",
+    "when",
+    "variables",
+    "beforeStreaming",
+    "onStreaming",
+    "afterStreaming",
+    "onStreamingEnd",
+];
+
+/// Parse and format `source` in canonical style, the diagnostics are returned if the source has errors.
+///
+/// Unlike [format_file], the entries of front matter are kept in source order with their comments, the
+/// comment lines stay before the entry they document and the trailing comments stay at the end of its
+/// line. The value with comments in it is kept as written, except that the variables are formatted one
+/// by one, and so is the entry overridden by a later one with the same key.
+pub fn format(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let result = parse(source);
+    let has_errors = result.has_errors();
+    let file = match result.file {
+        Some(file) if !has_errors => file,
+        _ => return Err(result.diagnostics),
+    };
+
+    let tree = parse_cst(source);
+    let mut output = match tree.child(SyntaxKind::FrontMatter) {
+        Some(front_matter) => format_front_matter(front_matter, source, &file.hobbit),
+        None => format_hobbit_hole(&file.hobbit),
+    };
+    output.push_str(&format_body(&file.body));
+    Ok(output)
+}
+
+/// Format the Shire file in canonical style, the output parses back to the same AST, and formatting
+/// the output again gives the same text.
+///
+/// The keys are printed in the order of [KEYS] followed by the user data, strings are double-quoted and
+/// blocks are indented by 2 spaces. The AST doesn't know where the comments are, so they are left out,
+/// use [format] to keep them.
+pub fn format_file(file: &ShireFile) -> String {
+    let mut output = format_hobbit_hole(&file.hobbit);
+    output.push_str(&format_body(&file.body));
+    output
+}
+
+fn format_hobbit_hole(hole: &HobbitHole) -> String {
+    let mut output = String::from("---\n");
+    for key in KEYS.into_iter().chain(hole.user_data.keys().map(|it| it.as_str())) {
+        if let Some(entry) = format_entry(hole, key) {
+            output.push_str(&format!("{}\n", entry));
+        }
+    }
+    output.push_str("---\n");
+    output
+}
+
+/// The entry of `key` in the hobbit hole, which may span multiple lines, `None` if the hole has no value of it
+fn format_entry(hole: &HobbitHole, key: &str) -> Option<String> {
+    match key {
+        "name" => (!hole.name.is_empty()).then(|| format!("name: {}", quote_string(&hole.name))),
+        "description" => hole.description.as_ref().map(|it| format!("description: {}", it)),
+        "interaction" => hole.interaction.as_ref().map(|it| format!("interaction: {}", it.name())),
+        "actionLocation" => hole.action_location.as_ref().map(|it| format!("actionLocation: {}", it.location())),
+        "model" => hole.model.as_ref().map(|it| format!("model:{}", format_value(it, 0))),
+        "when" => hole.when.as_ref().map(|it| format!("when: {}", it.display())),
+        "variables" => (!hole.variables.is_empty()).then(|| {
+            let variables = hole.variables.iter().map(|(key, value)| format_variable(key, value));
+            format!("variables:\n{}", variables.collect::<Vec<_>>().join("\n"))
+        }),
+        "beforeStreaming" => format_hook(key, &hole.before_streaming),
+        "onStreaming" => format_hook(key, &hole.on_streaming),
+        "afterStreaming" => format_hook(key, &hole.after_streaming),
+        "onStreamingEnd" => format_hook(key, &hole.on_streaming_end),
+        key => hole.user_data.get(key).map(|it| format!("{}:{}", format_key(key), format_value(it, 0))),
+    }
+}
+
+/// The front matter in the order of its entries, each comment is printed where it was, either in its own
+/// line or at the end of the entry before it.
+fn format_front_matter(front_matter: &SyntaxNode, source: &str, hole: &HobbitHole) -> String {
+    let mut output = String::from("---\n");
+    let keys = front_matter.nodes(SyntaxKind::Entry).map(entry_key).collect::<Vec<_>>();
+    let mut index = 0;
+    // whether the last line is the end of entry, which the comment in the same line trails
+    let mut trailing = false;
+    for child in front_matter.children.iter().skip_while(|it| it.kind() != SyntaxKind::Delimiter).skip(1) {
+        match child {
+            SyntaxElement::Node(entry) => {
+                // the value in `hole` is of the last entry with the key, the earlier ones are kept as written
+                let overridden = keys[index].is_some() && keys[index + 1..].contains(&keys[index]);
+                let formatted = match overridden {
+                    true => source[entry.span.start..entry.span.end].to_string(),
+                    false => format_source_entry(entry, source, hole),
+                };
+                output.push_str(&format!("{}\n", formatted));
+                index += 1;
+                trailing = true;
+            }
+            SyntaxElement::Token(token) => match token.kind {
+                SyntaxKind::Newline => trailing = false,
+                SyntaxKind::Comment if trailing => {
+                    output.pop();
+                    output.push_str(&format!(" {}\n", token.text));
+                }
+                SyntaxKind::Comment => output.push_str(&format!("{}\n", token.text)),
+                _ => {}
+            },
+        }
+    }
+
+    output.push_str("---\n");
+    output
+}
+
+/// The entry of syntax tree formatted with the value in `hole`, it's kept as written if the value has comments
+/// in it, so that the comments are not moved or lost.
+fn format_source_entry(entry: &SyntaxNode, source: &str, hole: &HobbitHole) -> String {
+    let written = source[entry.span.start..entry.span.end].to_string();
+    let key = entry_key(entry);
+    let value = entry.child(SyntaxKind::Value);
+    let has_comments = value.is_some_and(|it| it.tokens().iter().any(|token| token.kind == SyntaxKind::Comment));
+
+    match (key.as_deref(), value) {
+        (Some("variables"), Some(value)) if has_comments => format_source_variables(value, source, hole),
+        _ if has_comments => written,
+        (Some(key), _) => format_entry(hole, key).unwrap_or(written),
+        (None, _) => written,
+    }
+}
+
+/// The key of entry in syntax tree, for example: `name` of `name: "Summary"` and `top p` of `"top p": 1`
+fn entry_key(entry: &SyntaxNode) -> Option<String> {
+    entry.children.iter().find_map(|it| match it {
+        SyntaxElement::Token(token) if token.kind == SyntaxKind::Key => {
+            parse_object_key(&token.text).ok().map(|(_, key)| key)
+        }
+        _ => None,
+    })
+}
+
+/// The variable in the value of `variables:` entry with the comments around it
+#[derive(Default)]
+struct SourceVariable {
+    key: String,
+    /// The comment lines before the variable
+    leading: Vec<String>,
+    /// The span from the key to the end of value
+    span: Span,
+    /// Whether there are comments in the value, then the variable is kept as written
+    has_comments: bool,
+    /// The comment at the end of the last line of variable
+    trailing: Option<String>,
+}
+
+/// The `variables:` entry with comments in its `value`, each variable is formatted with the value in `hole`
+/// unless it has comments in it, then it's kept as written.
+fn format_source_variables(value: &SyntaxNode, source: &str, hole: &HobbitHole) -> String {
+    let tokens = tokenize_front_matter(source, value.span.start);
+    let tokens = tokens.iter().filter(|it| it.span.end <= value.span.end).collect::<Vec<_>>();
+    let line_start = |index: usize| source[..index].trim_end_matches([' ', '\t']).ends_with('\n');
+
+    let mut header = None;
+    let mut variables: Vec<SourceVariable> = vec![];
+    // the comment lines which are not yet known to be before a variable or in it
+    let mut pending = vec![];
+    let mut depth = 0;
+    for (index, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::Whitespace | TokenKind::Newline => {}
+            TokenKind::Comment => match variables.last_mut() {
+                None if !line_start(token.span.start) => header = Some(token.text.to_string()),
+                None => pending.push(token.text.to_string()),
+                Some(_) if depth == 0 && line_start(token.span.start) => pending.push(token.text.to_string()),
+                Some(variable) if depth == 0 && !source[variable.span.end..token.span.start].contains('\n') => {
+                    variable.trailing = Some(token.text.to_string())
+                }
+                Some(variable) => variable.has_comments = true,
+            },
+            kind => {
+                let next = tokens[index + 1..].iter().find(|it| !it.kind.is_trivia());
+                let is_key = depth == 0
+                    && kind == TokenKind::String
+                    && line_start(token.span.start)
+                    && next.is_some_and(|it| it.kind == TokenKind::Colon);
+
+                match variables.last_mut() {
+                    Some(variable) if !is_key => {
+                        // the comments before it are in the value of variable
+                        variable.has_comments |= !pending.is_empty() || variable.trailing.is_some();
+                        pending.clear();
+                        variable.trailing = None;
+                        variable.span.end = token.span.end;
+                    }
+                    _ => variables.push(SourceVariable {
+                        key: parse_quoted_string(token.text).map(|(_, key)| key).unwrap_or_default(),
+                        leading: std::mem::take(&mut pending),
+                        span: token.span,
+                        ..Default::default()
+                    }),
+                }
+
+                match kind {
+                    TokenKind::LeftBrace | TokenKind::LeftParen | TokenKind::LeftBracket => depth += 1,
+                    TokenKind::RightBrace | TokenKind::RightParen | TokenKind::RightBracket => depth -= 1,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let mut output = match header {
+        Some(comment) => format!("variables: {}", comment),
+        None => String::from("variables:"),
+    };
+    for variable in &variables {
+        for comment in &variable.leading {
+            output.push_str(&format!("\n  {}", comment));
+        }
+
+        let written = &source[variable.span.start..variable.span.end];
+        match hole.variables.get(&variable.key) {
+            Some(value) if !variable.has_comments => output.push_str(&format!("\n{}", format_variable(&variable.key, value))),
+            _ => output.push_str(&format!("\n  {}", written)),
+        }
+        if let Some(comment) = &variable.trailing {
+            output.push_str(&format!(" {}", comment));
+        }
+    }
+    for comment in pending {
+        output.push_str(&format!("\n  {}", comment));
+    }
+
+    output
+}
+
+/// The block in braces, the lines of `content` are indented one level deeper than the closing brace
+fn format_block(content: &str, indent: usize) -> String {
+    let padding = " ".repeat(indent + 2);
+    let lines = content.lines().map(|line| format!("{}{}", padding, line)).collect::<Vec<_>>();
+    format!("{{\n{}\n{}}}", lines.join("\n"), " ".repeat(indent))
+}
+
+/// The pipeline in braces, which is in one line if it fits in [MAX_WIDTH] after `prefix`,
+/// otherwise each function is in its own line with `|` aligned under the first function.
+fn format_pipeline(functions: &[String], prefix: usize, indent: usize) -> String {
+    let line = format!("{{ {} }}", functions.join(" | "));
+    if prefix + line.len() <= MAX_WIDTH || functions.len() < 2 {
+        return line;
+    }
+
+    let padding = " ".repeat(indent + 2);
+    let separator = format!("\n{}| ", padding);
+    format!("{{\n{}{}\n{}}}", padding, functions.join(&separator), " ".repeat(indent))
+}
+
+fn format_call(name: &str, args: &[FunctionArg]) -> String {
+    match args.is_empty() {
+        true => name.to_string(),
+        false => format!("{}({})", name, args.iter().map(|it| it.to_string()).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+fn format_function(function: &Function) -> Vec<String> {
+    match function {
+        Function::Functions(calls) => calls.iter().map(|(name, args)| format_call(name, args)).collect(),
+    }
+}
+
+fn format_query(query: &ShirePsiQueryStatement, indent: usize) -> String {
+    let from = query.from.iter().map(|it| it.to_string()).collect::<Vec<_>>().join(", ");
+    let select = query.select.iter().map(|it| it.display()).collect::<Vec<_>>().join(", ");
+    let clauses = format!(
+        "from {}\nwhere {}\nselect {}",
+        format_block(&from, 0),
+        format_block(&query.where_clause.display(), 0),
+        format_block(&select, 0)
+    );
+    format_block(&clauses, indent)
+}
+
+fn format_variable(key: &str, value: &VariableTransform) -> String {
+    let prefix = format!("  {}: ", quote_string(key));
+    let value = match value {
+        VariableTransform::String(value) => quote_string(value),
+        VariableTransform::Integer(value) => value.to_string(),
+        VariableTransform::Float(value) => format!("{:?}", value),
+        VariableTransform::Boolean(value) => value.to_string(),
+        VariableTransform::PatternAction { pattern, command } => {
+            let pattern = pattern.to_string();
+            let pipeline = format_pipeline(&format_function(command), prefix.len() + pattern.len() + 1, 2);
+            format!("{} {}", pattern, pipeline)
+        }
+        VariableTransform::Action { command } => format_pipeline(&format_function(command), prefix.len(), 2),
+        VariableTransform::Case { pattern, case } => format!("{} {}", pattern, format_block(&case.display(), 2)),
+        VariableTransform::Query(query) => format_query(query, 2),
+    };
+
+    format!("{}{}", prefix, value)
+}
+
+/// The hook is printed from its calls as they are written, like the pipeline of [format_variable]
+fn format_hook(key: &str, hook: &Option<LifecycleHook>) -> Option<String> {
+    let value = match hook.as_ref()? {
+        LifecycleHook::Action { command } => format_pipeline(&format_function(command), key.len() + 2, 0),
+        LifecycleHook::Case { case } => format_block(&case.display(), 0),
+    };
+
+    Some(format!("{}: {}", key, value))
+}
+
+fn format_key(key: &str) -> String {
+    match !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+        true => key.to_string(),
+        false => quote_string(key),
+    }
+}
+
+/// The flow array like `["a", 1]` only holds scalars and flow arrays
+fn is_flow(value: &FrontMatterType) -> bool {
+    match value {
+        FrontMatterType::ARRAY(items) => items.iter().all(is_flow),
+        FrontMatterType::OBJECT(_) | FrontMatterType::EMPTY => false,
+        _ => true,
+    }
+}
+
+fn format_scalar(value: &FrontMatterType) -> String {
+    match value {
+        FrontMatterType::STRING(value) => quote_string(value),
+        FrontMatterType::EMPTY => String::new(),
+        FrontMatterType::ARRAY(items) => {
+            format!("[{}]", items.iter().map(format_scalar).collect::<Vec<_>>().join(", "))
+        }
+        value => value.display(),
+    }
+}
+
+/// The value after `key:`, which is a scalar in the same line, or a block in the following lines
+/// indented deeper than `indent`.
+fn format_value(value: &FrontMatterType, indent: usize) -> String {
+    match value {
+        FrontMatterType::OBJECT(object) if object.is_empty() => String::new(),
+        FrontMatterType::OBJECT(object) => format!("\n{}", format_object(object, indent + 2)),
+        FrontMatterType::ARRAY(items) if !is_flow(value) => format!("\n{}", format_array(items, indent + 2)),
+        FrontMatterType::EMPTY => String::new(),
+        value => format!(" {}", format_scalar(value)),
+    }
+}
+
+/// The entries of object in declaration order
+fn format_object(object: &IndexMap<String, FrontMatterType>, indent: usize) -> String {
+    object
+        .iter()
+        .map(|(key, value)| format!("{}{}:{}", " ".repeat(indent), format_key(key), format_value(value, indent)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_array(items: &[FrontMatterType], indent: usize) -> String {
+    items
+        .iter()
+        .map(|item| match item {
+            // the compact form, the first entry follows `- ` and the others are aligned with it
+            FrontMatterType::OBJECT(object) if !object.is_empty() => {
+                format!("{}- {}", " ".repeat(indent), &format_object(object, indent + 2)[indent + 2..])
+            }
+            item => format!("{}- {}", " ".repeat(indent), format_scalar(item)),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_body(nodes: &[BodyNode]) -> String {
+    let mut output = String::new();
+    for (index, node) in nodes.iter().enumerate() {
+        match &node.kind {
+            BodyNodeKind::Text(text) => output.push_str(text),
+            BodyNodeKind::Variable(name) => {
+                // the braces are needed if the name would run into the following text
+                let joined = match nodes.get(index + 1).map(|it| &it.kind) {
+                    Some(BodyNodeKind::Text(text)) => text.starts_with(|c: char| c.is_alphanumeric() || c == '_'),
+                    _ => false,
+                };
+                match joined {
+                    true => output.push_str(&format!("${{{}}}", name)),
+                    false => output.push_str(&format!("${}", name)),
+                }
+            }
+            BodyNodeKind::Command { name, argument } => match argument {
+                Some(argument) => output.push_str(&format!("/{}:{}", name, argument)),
+                None => output.push_str(&format!("/{}", name)),
+            },
+            BodyNodeKind::Agent(name) => output.push_str(&format!("@{}", name)),
+            BodyNodeKind::Toolchain(name) => output.push_str(&format!("#{}", name)),
+            BodyNodeKind::CodeBlock { language, code } => match code.is_empty() {
+                true => output.push_str(&format!("```{}\n```", language)),
+                false => output.push_str(&format!("```{}\n{}\n```", language, code)),
+            },
+            BodyNodeKind::Comment(text) => output.push_str(&format!("[#]: # ({})\n", text)),
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_canonical(source: &str, expected: &str) {
+        let formatted = format(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+
+        let original = parse(source).file.unwrap();
+        let reparsed = parse(&formatted).file.unwrap();
+        assert_eq!(reparsed.hobbit, original.hobbit);
+        assert_eq!(
+            reparsed.body.into_iter().map(|it| it.kind).collect::<Vec<_>>(),
+            original.body.into_iter().map(|it| it.kind).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            reparsed.comments.into_iter().map(|it| it.text).collect::<Vec<_>>(),
+            original.comments.into_iter().map(|it| it.text).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_format_file() {
+        let source = r#"
+---
+variables:
+    "var1":   'demo'
+    // the files to review
+    "var2": /.*\.java/ {grep("error.log")|sort}
+    "var3": /.*\.log/ { case "$0" { "error" { grep("ERROR") } default { sort } } }
+    "var4": { from { PsiClass clazz } where { clazz.getName() == "Foo" } select { clazz.id, clazz.name } }
+when:   $fileName.contains(".java")&&!$isTest
+# TODO: review
+name: 'Summary'
+interaction:  AppendCursor
+model:
+  temperature: 0.5
+  provider: openai
+  stop: [ "END", 42 ]
+  tools:
+    - name: grep
+      args: ["-n"]
+afterStreaming: {  parseCode|saveFile("out.java")  }
+---
+[//]: # (explain briefly)
+Explain ${selection}s in $fileName with #kotlin
+"#;
+
+        let expected = r#"---
+variables:
+  "var1": "demo"
+  // the files to review
+  "var2": /.*\.java/ { grep("error.log") | sort }
+  "var3": /.*\.log/ {
+    case "$0" {
+      "error" { grep("ERROR") }
+      default { sort }
+    }
+  }
+  "var4": {
+    from {
+      PsiClass clazz
+    }
+    where {
+      clazz.getName() == "Foo"
+    }
+    select {
+      clazz.id, clazz.name
+    }
+  }
+when: $fileName.contains(".java") && !$isTest
+# TODO: review
+name: "Summary"
+interaction: AppendCursor
+model:
+  temperature: 0.5
+  provider: "openai"
+  stop: ["END", 42]
+  tools:
+    - name: "grep"
+      args: ["-n"]
+afterStreaming: { parseCode | saveFile("out.java") }
+---
+[#]: # (explain briefly)
+Explain ${selection}s in $fileName with #kotlin
+"#;
+
+        assert_canonical(source, expected);
+    }
+
+    #[test]
+    fn test_keep_comments_in_place() {
+        let source = r#"---
+# the name in the menu
+name:   'Review'  # shown to users
+variables:  # the inputs
+    "files": /.*\.rs/ {
+      grep("TODO") # only the todos
+      | sort
+    }
+    # the extension
+    "ext":  'rs'   // without dot
+    # end of variables
+model:
+  # the cheap one
+  provider: openai
+config:
+  retries: 3
+  backoff:
+    - 1
+    - 2
+---
+Review $files
+"#;
+
+        let expected = r#"---
+# the name in the menu
+name: "Review" # shown to users
+variables: # the inputs
+  "files": /.*\.rs/ {
+      grep("TODO") # only the todos
+      | sort
+    }
+  # the extension
+  "ext": "rs" // without dot
+# end of variables
+model:
+  # the cheap one
+  provider: openai
+config:
+  retries: 3
+  backoff: [1, 2]
+---
+Review $files
+"#;
+
+        assert_canonical(source, expected);
+        assert!(format_file(&parse(source).file.unwrap()).contains("config:\n  retries: 3\n  backoff: [1, 2]\n"));
+    }
+
+    #[test]
+    fn test_keep_overridden_entry_as_written() {
+        let source = "---\nname:   \"a\"\ninteraction: AppendCursor\nname: 'b'\n---\n";
+        let expected = "---\nname:   \"a\"\ninteraction: AppendCursor\nname: \"b\"\n---\n";
+
+        assert_canonical(source, expected);
+    }
+
+    #[test]
+    fn test_break_long_pipeline() {
+        let source = "---\nvariables:\n  \"files\": /.*\\.rs/ { grep(\"error\", \"warning\") | sort | uniq | head(10) | xargs(\"notify_admin\") }\n---\n";
+        let expected = r#"---
+variables:
+  "files": /.*\.rs/ {
+    grep("error", "warning")
+    | sort
+    | uniq
+    | head(10)
+    | xargs("notify_admin")
+  }
+---
+"#;
+
+        assert_canonical(source, expected);
+    }
+
+    #[test]
+    fn test_format_hooks_as_written() {
+        let source = r#"---
+afterStreaming: {head|sed($p, "x")|foo(n=5)}
+onStreaming: {print(trim($name))|tail(n=5)}
+---
+"#;
+        let expected = r#"---
+afterStreaming: { head | sed($p, "x") | foo(n=5) }
+onStreaming: { print(trim($name)) | tail(n=5) }
+---
+"#;
+
+        assert_canonical(source, expected);
+        let canonical = r#"---
+onStreaming: { print(trim($name)) | tail(n=5) }
+afterStreaming: { head | sed($p, "x") | foo(n=5) }
+---
+"#;
+        assert_eq!(format_file(&parse(source).file.unwrap()), canonical);
+    }
+
+    #[test]
+    fn test_format_user_data_keys() {
+        let source = "---\nmax_tokens:   1\n\"top p\": 0.5\nmodel:\n  top-p:   0.5\n---\n";
+        let expected = "---\nmax_tokens: 1\n\"top p\": 0.5\nmodel:\n  top-p: 0.5\n---\n";
+        assert_canonical(source, expected);
+    }
+}
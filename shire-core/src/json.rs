@@ -0,0 +1,83 @@
+use crate::parser::ShireFile;
+use serde::{Deserialize, Serialize};
+
+/// The version of JSON representation, which is increased when the AST changes incompatibly
+pub const AST_VERSION: u64 = 1;
+
+#[derive(Serialize)]
+struct DocumentRef<'a> {
+    version: u64,
+    file: &'a ShireFile,
+}
+
+#[derive(Deserialize)]
+struct Document {
+    file: ShireFile,
+}
+
+/// Serialize the parsed file to JSON with its spans, for example:
+///
+/// ```json
+/// {"version":1,"file":{"hobbit":{"name":"Summary",...},"body":[...],"comments":[]}}
+/// ```
+///
+/// the regex is kept as its pattern and flags, and object keys are kept in declaration order.
+pub fn to_json(file: &ShireFile) -> String {
+    // the keys of all maps are strings, so the serialization never fails
+    serde_json::to_string(&DocumentRef { version: AST_VERSION, file }).expect("the AST is serializable")
+}
+
+/// Load the file from JSON created by [to_json], the JSON of other versions is rejected.
+pub fn from_json(json: &str) -> Result<ShireFile, String> {
+    let value: serde_json::Value = serde_json::from_str(json).map_err(|err| err.to_string())?;
+    match value.get("version").and_then(|it| it.as_u64()) {
+        Some(AST_VERSION) => {}
+        Some(version) => return Err(format!("unsupported AST version `{}`, expected `{}`", version, AST_VERSION)),
+        None => return Err("missing AST version".to_string()),
+    }
+
+    serde_json::from_value::<Document>(value).map(|document| document.file).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_json_round_trip() {
+        let source = r#"---
+# TODO
+name: "Summary"
+interaction: AppendCursor
+when: $fileName.endsWith(".java") && $size > 10
+variables:
+  "files": /.*\.java/i { grep("class", n=5) | sort }
+  "log": /.*\.log/ { case "$0" { "error" { grep("ERROR") } default { sort } } }
+  "score": 0.75
+model:
+  provider: "openai"
+  stop: ["END"]
+afterStreaming: { parseCode | saveFile("out.java") }
+---
+Explain $selection
+"#;
+
+        let file = parse(source).file.unwrap();
+        let json = to_json(&file);
+        assert!(json.starts_with(r#"{"version":1,"file":{"hobbit":{"name":"Summary""#));
+        assert!(json.contains(r#"{"pattern":".*\\.java","flags":"i"}"#));
+
+        let loaded = from_json(&json).unwrap();
+        assert_eq!(loaded, file);
+        assert_eq!(loaded.body[1].span, file.body[1].span);
+        assert_eq!(to_json(&loaded), json);
+    }
+
+    #[test]
+    fn test_reject_other_version() {
+        let json = r#"{"version":2,"file":{}}"#;
+        assert_eq!(from_json(json).unwrap_err(), "unsupported AST version `2`, expected `1`");
+        assert_eq!(from_json("{}").unwrap_err(), "missing AST version");
+    }
+}
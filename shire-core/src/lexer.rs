@@ -0,0 +1,358 @@
+use crate::body::{parse_body, BodyNodeKind};
+use crate::cst::parse_front_matter;
+use crate::parser::{number_literal, parse_quoted_string, regex_literal};
+use crate::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Whitespace,
+    Newline,
+    /// The comment to the end of line, for example: `# TODO` or `// note` in front matter, or `[#]: # (TODO)`
+    /// in body
+    Comment,
+    /// The `---` line around front matter
+    Delimiter,
+    Identifier,
+    /// The quoted string, for example: `"error"`, `'error'` or `"""multi-line"""`
+    String,
+    Number,
+    /// The regex literal, for example: `/.*\.java/i`
+    Regex,
+    /// The variable reference, for example: `$fileName` or `${fileName}` in body
+    Variable,
+    Colon,
+    Comma,
+    Dot,
+    Minus,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Pipe,
+    Not,
+    Assign,
+    And,
+    Or,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Plus,
+    Star,
+    /// The division operator, `/` after an operand, otherwise it starts a regex
+    Slash,
+    Percent,
+    Question,
+    /// The null-coalescing operator `??`
+    Coalesce,
+    /// The plain text of body
+    Text,
+    /// The command line of body, for example: `/file:src/main.rs`
+    Command,
+    /// The agent mention of body, for example: `@reviewer`
+    Agent,
+    /// The toolchain reference of body, for example: `#kotlin`
+    Toolchain,
+    /// The fenced code block of body
+    CodeBlock,
+    /// The text which can't start any token, for example: the unterminated string
+    Unknown,
+}
+
+impl TokenKind {
+    /// Trivia is the whitespace and comment, which has no effect on the AST
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, TokenKind::Whitespace | TokenKind::Newline | TokenKind::Comment)
+    }
+
+    /// The operand can't be followed by regex, so `/` after it is an operator
+    fn is_operand(&self) -> bool {
+        matches!(
+            self,
+            TokenKind::Identifier
+                | TokenKind::String
+                | TokenKind::Number
+                | TokenKind::Regex
+                | TokenKind::Variable
+                | TokenKind::RightParen
+                | TokenKind::RightBracket
+        )
+    }
+}
+
+/// The token of Shire, the `text` is the slice of source and `span` is its position in the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    pub span: Span,
+}
+
+/// The operators and punctuations, the longer ones are tried first
+const OPERATORS: [(&str, TokenKind); 27] = [
+    ("??", TokenKind::Coalesce),
+    ("||", TokenKind::Or),
+    ("&&", TokenKind::And),
+    ("==", TokenKind::Equal),
+    ("!=", TokenKind::NotEqual),
+    ("<=", TokenKind::LessEqual),
+    (">=", TokenKind::GreaterEqual),
+    ("|", TokenKind::Pipe),
+    ("!", TokenKind::Not),
+    ("<", TokenKind::Less),
+    (">", TokenKind::Greater),
+    ("+", TokenKind::Plus),
+    ("*", TokenKind::Star),
+    ("/", TokenKind::Slash),
+    ("%", TokenKind::Percent),
+    ("?", TokenKind::Question),
+    ("=", TokenKind::Assign),
+    (":", TokenKind::Colon),
+    (",", TokenKind::Comma),
+    (".", TokenKind::Dot),
+    ("-", TokenKind::Minus),
+    ("(", TokenKind::LeftParen),
+    (")", TokenKind::RightParen),
+    ("{", TokenKind::LeftBrace),
+    ("}", TokenKind::RightBrace),
+    ("[", TokenKind::LeftBracket),
+    ("]", TokenKind::RightBracket),
+];
+
+fn line_len(input: &str) -> usize {
+    let line = &input[..input.find('\n').unwrap_or(input.len())];
+    line.trim_end_matches([' ', '\t', '\r']).len()
+}
+
+fn identifier_len(input: &str) -> usize {
+    input.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(input.len())
+}
+
+/// The kind and length of the token at the start of `input`, `line_start` is whether only whitespaces
+/// are before it in the line, `after_space` is whether it's at the start of line or after a whitespace,
+/// and `after_operand` decides if `/` starts a regex.
+///
+/// The comment starts with `#` or `//` after a whitespace, so `C#` and `https://` are not comments,
+/// and the markers in strings and regex literals are part of them.
+fn next_token(input: &str, line_start: bool, after_space: bool, after_operand: bool) -> (TokenKind, usize) {
+    let consumed = |rest: &str| input.len() - rest.len();
+    let mut chars = input.chars();
+    let c = chars.next().unwrap_or_default();
+    let next = chars.next().unwrap_or_default();
+
+    match c {
+        '\n' => (TokenKind::Newline, 1),
+        '\r' if next == '\n' => (TokenKind::Newline, 2),
+        ' ' | '\t' | '\r' => {
+            (TokenKind::Whitespace, input.find(|c: char| !matches!(c, ' ' | '\t' | '\r')).unwrap_or(input.len()))
+        }
+        '-' if line_start && input.starts_with("---") => (TokenKind::Delimiter, 3),
+        '#' if after_space => (TokenKind::Comment, line_len(input)),
+        '/' if after_space && next == '/' => (TokenKind::Comment, line_len(input)),
+        '"' | '\'' => match parse_quoted_string(input) {
+            Ok((rest, _)) => (TokenKind::String, consumed(rest)),
+            Err(_) => (TokenKind::Unknown, line_len(input)),
+        },
+        '/' if !after_operand => match regex_literal(input) {
+            Ok((rest, _)) => (TokenKind::Regex, consumed(rest)),
+            Err(_) => (TokenKind::Unknown, 1),
+        },
+        '$' if next.is_alphabetic() || next == '_' => (TokenKind::Variable, 1 + identifier_len(&input[1..])),
+        '-' if !after_operand && next.is_ascii_digit() => match number_literal(input) {
+            Ok((rest, _)) => (TokenKind::Number, consumed(rest)),
+            Err(_) => (TokenKind::Minus, 1),
+        },
+        c if c.is_ascii_digit() => match number_literal(input) {
+            Ok((rest, _)) => (TokenKind::Number, consumed(rest)),
+            Err(_) => (TokenKind::Unknown, 1),
+        },
+        c if c.is_alphabetic() || c == '_' => (TokenKind::Identifier, identifier_len(input)),
+        c => OPERATORS
+            .iter()
+            .find(|(operator, _)| input.starts_with(operator))
+            .map(|(operator, kind)| (*kind, operator.len()))
+            .unwrap_or((TokenKind::Unknown, c.len_utf8())),
+    }
+}
+
+/// Tokenize the code from `start` of `source`, stops after the first `---` line if `until_delimiter`
+fn tokenize_from(source: &str, start: usize, until_delimiter: bool) -> Vec<Token<'_>> {
+    let mut tokens: Vec<Token> = vec![];
+    let mut index = start;
+    while index < source.len() {
+        let before = &source[..index];
+        let line_start = before.trim_end_matches([' ', '\t']).ends_with('\n') || index == 0;
+        let after_space = before.is_empty() || before.ends_with(char::is_whitespace);
+        let after_operand = tokens.iter().rev().find(|it| !it.kind.is_trivia()).is_some_and(|it| it.kind.is_operand());
+
+        let (kind, length) = next_token(&source[index..], line_start, after_space, after_operand);
+        tokens.push(Token { kind, text: &source[index..index + length], span: Span::new(index, index + length) });
+        index += length;
+        if until_delimiter && kind == TokenKind::Delimiter {
+            break;
+        }
+    }
+
+    tokens
+}
+
+/// Tokenize the code like front matter or expression, for example: `when: $fileName.contains(".java")`,
+/// the spans are the positions in `source`.
+pub fn tokenize_code(source: &str) -> Vec<Token<'_>> {
+    tokenize_from(source, 0, false)
+}
+
+/// Tokenize the front matter from `start` of `source` which is after the opening `---`, to the closing `---`
+/// which is the last token, or to the end of source if it's not closed.
+pub(crate) fn tokenize_front_matter(source: &str, start: usize) -> Vec<Token<'_>> {
+    tokenize_from(source, start, true)
+}
+
+/// Tokenize the Shire file, the front matter is tokenized as code and the body as prompt text with
+/// variables, commands, agents, toolchains, code blocks and comments.
+///
+/// The tokens cover the whole source without gaps, so they can be used for highlighting as well.
+pub fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let body_start = parse_front_matter(source, 0).map(|it| it.span.end).unwrap_or(0);
+    let mut tokens = tokenize_code(&source[..body_start]);
+
+    tokens.extend(parse_body(&source[body_start..], body_start).into_iter().map(|node| {
+        let kind = match node.kind {
+            BodyNodeKind::Text(_) => TokenKind::Text,
+            BodyNodeKind::Variable(_) => TokenKind::Variable,
+            BodyNodeKind::Command { .. } => TokenKind::Command,
+            BodyNodeKind::Agent(_) => TokenKind::Agent,
+            BodyNodeKind::Toolchain(_) => TokenKind::Toolchain,
+            BodyNodeKind::CodeBlock { .. } => TokenKind::CodeBlock,
+            BodyNodeKind::Comment(_) => TokenKind::Comment,
+        };
+        Token { kind, text: &source[node.span.start..node.span.end], span: node.span }
+    }));
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(tokens: Vec<Token<'_>>) -> Vec<(TokenKind, &str)> {
+        tokens.into_iter().filter(|it| !it.kind.is_trivia()).map(|it| (it.kind, it.text)).collect()
+    }
+
+    #[test]
+    fn test_tokenize_code() {
+        let source = r#"when: $fileName.endsWith(".kt") && !$isTest || $size != -1"#;
+        assert_eq!(
+            kinds(tokenize_code(source)),
+            vec![
+                (TokenKind::Identifier, "when"),
+                (TokenKind::Colon, ":"),
+                (TokenKind::Variable, "$fileName"),
+                (TokenKind::Dot, "."),
+                (TokenKind::Identifier, "endsWith"),
+                (TokenKind::LeftParen, "("),
+                (TokenKind::String, "\".kt\""),
+                (TokenKind::RightParen, ")"),
+                (TokenKind::And, "&&"),
+                (TokenKind::Not, "!"),
+                (TokenKind::Variable, "$isTest"),
+                (TokenKind::Or, "||"),
+                (TokenKind::Variable, "$size"),
+                (TokenKind::NotEqual, "!="),
+                (TokenKind::Number, "-1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_regex_and_pipeline() {
+        let source = r#""var": /a{2}|\/b/i { grep("x|y") | head(n=5) } // note"#;
+        assert_eq!(
+            kinds(tokenize_code(source)),
+            vec![
+                (TokenKind::String, "\"var\""),
+                (TokenKind::Colon, ":"),
+                (TokenKind::Regex, r"/a{2}|\/b/i"),
+                (TokenKind::LeftBrace, "{"),
+                (TokenKind::Identifier, "grep"),
+                (TokenKind::LeftParen, "("),
+                (TokenKind::String, "\"x|y\""),
+                (TokenKind::RightParen, ")"),
+                (TokenKind::Pipe, "|"),
+                (TokenKind::Identifier, "head"),
+                (TokenKind::LeftParen, "("),
+                (TokenKind::Identifier, "n"),
+                (TokenKind::Assign, "="),
+                (TokenKind::Number, "5"),
+                (TokenKind::RightParen, ")"),
+                (TokenKind::RightBrace, "}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_comments() {
+        let source = "# TODO\nname: \"C# a\" # trailing\ndescription: C# at https://a.com/#b // why\n  \"v\": /#x/ //";
+        let comments: Vec<&str> =
+            tokenize_code(source).into_iter().filter(|it| it.kind == TokenKind::Comment).map(|it| it.text).collect();
+        assert_eq!(comments, vec!["# TODO", "# trailing", "// why", "//"]);
+    }
+
+    #[test]
+    fn test_tokenize_arithmetic() {
+        let source = r#"$size / 2 % 3 > ($max ?? 1) ? $a + "x" : /b/"#;
+        let kinds: Vec<TokenKind> = kinds(tokenize_code(source)).into_iter().map(|(kind, _)| kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Variable,
+                TokenKind::Slash,
+                TokenKind::Number,
+                TokenKind::Percent,
+                TokenKind::Number,
+                TokenKind::Greater,
+                TokenKind::LeftParen,
+                TokenKind::Variable,
+                TokenKind::Coalesce,
+                TokenKind::Number,
+                TokenKind::RightParen,
+                TokenKind::Question,
+                TokenKind::Variable,
+                TokenKind::Plus,
+                TokenKind::String,
+                TokenKind::Colon,
+                TokenKind::Regex,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_file() {
+        let source = "---\n# TODO\nname: \"Summary\"\n---\nAsk @reviewer about $selection with #kotlin\n/file:a.rs\n";
+        let tokens = tokenize(source);
+        assert_eq!(tokens.iter().map(|it| it.text).collect::<String>(), source);
+        assert_eq!(tokens.first().map(|it| it.kind), Some(TokenKind::Delimiter));
+        assert_eq!(tokens[2], Token { kind: TokenKind::Comment, text: "# TODO", span: Span::new(4, 10) });
+
+        let body = kinds(tokens.into_iter().skip_while(|it| it.kind != TokenKind::Text).collect());
+        assert_eq!(
+            body,
+            vec![
+                (TokenKind::Text, "Ask "),
+                (TokenKind::Agent, "@reviewer"),
+                (TokenKind::Text, " about "),
+                (TokenKind::Variable, "$selection"),
+                (TokenKind::Text, " with "),
+                (TokenKind::Toolchain, "#kotlin"),
+                (TokenKind::Text, "\n"),
+                (TokenKind::Command, "/file:a.rs"),
+                (TokenKind::Text, "\n"),
+            ]
+        );
+    }
+}
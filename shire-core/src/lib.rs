@@ -1,3 +1,14 @@
 pub mod markdown;
 pub mod parser;
 pub mod ast;
+pub mod diagnostic;
+pub mod span;
+pub mod body;
+pub mod expression;
+pub mod cst;
+pub mod format;
+pub mod lexer;
+pub mod json;
+pub mod runtime;
+pub mod methods;
+pub mod compare;
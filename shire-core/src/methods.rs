@@ -0,0 +1,458 @@
+use crate::compare::equals;
+use crate::runtime::Value;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// The error of method call, which is reported instead of panicking, for example: `$a.substring(10)` on a
+/// short string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MethodError {
+    /// The method is not registered or doesn't support the receiver, for example: `$a.foo()`
+    UnknownMethod { name: String, receiver: &'static str },
+    /// The number of arguments is out of `min..=max`, for example: `$a.contains()`
+    ArgumentCount { name: String, min: usize, max: usize, found: usize },
+    /// The argument has wrong type, for example: `$a.split(1)`
+    TypeMismatch { name: String, index: usize, expected: &'static str, found: &'static str },
+    /// The argument is invalid for the method, for example: the invalid regex or `"x".toInt()`
+    InvalidArgument { name: String, message: String },
+}
+
+impl fmt::Display for MethodError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MethodError::UnknownMethod { name, receiver } => write!(f, "unknown method `{}` for {}", name, receiver),
+            MethodError::ArgumentCount { name, min, max, found } => {
+                let (expected, count) = match (min, max) {
+                    (min, max) if min == max => (min.to_string(), min),
+                    (min, &usize::MAX) => (format!("at least {}", min), min),
+                    (min, max) => (format!("{} to {}", min, max), max),
+                };
+                let plural = if *count == 1 { "argument" } else { "arguments" };
+                write!(f, "`{}` expects {} {}, found {}", name, expected, plural, found)
+            }
+            MethodError::TypeMismatch { name, index, expected, found } => {
+                write!(f, "`{}` expects {} for argument {}, found {}", name, expected, index + 1, found)
+            }
+            MethodError::InvalidArgument { name, message } => write!(f, "`{}`: {}", name, message),
+        }
+    }
+}
+
+/// The call of method with evaluated receiver and arguments, the helpers check the arguments and
+/// create the errors with the method name.
+pub struct Invocation<'v> {
+    pub name: &'v str,
+    pub receiver: &'v Value,
+    pub args: &'v [Value],
+}
+
+impl<'v> Invocation<'v> {
+    /// Check the number of arguments is in `min..=max`
+    pub fn arity(&self, min: usize, max: usize) -> Result<(), MethodError> {
+        match (min..=max).contains(&self.args.len()) {
+            true => Ok(()),
+            false => Err(MethodError::ArgumentCount { name: self.name.to_string(), min, max, found: self.args.len() }),
+        }
+    }
+
+    pub fn arg(&self, index: usize) -> Result<&'v Value, MethodError> {
+        self.args.get(index).ok_or_else(|| MethodError::ArgumentCount {
+            name: self.name.to_string(),
+            min: index + 1,
+            max: usize::MAX,
+            found: self.args.len(),
+        })
+    }
+
+    pub fn str_arg(&self, index: usize) -> Result<&'v str, MethodError> {
+        let arg = self.arg(index)?;
+        arg.as_str().ok_or_else(|| self.type_mismatch(index, "string", arg))
+    }
+
+    pub fn int_arg(&self, index: usize) -> Result<i64, MethodError> {
+        let arg = self.arg(index)?;
+        arg.as_int().ok_or_else(|| self.type_mismatch(index, "int", arg))
+    }
+
+    /// The regex compiled from the string argument
+    pub fn regex_arg(&self, index: usize) -> Result<Regex, MethodError> {
+        let pattern = self.str_arg(index)?;
+        Regex::new(pattern).map_err(|_| self.invalid(format!("invalid regex `{}`", pattern)))
+    }
+
+    /// The text of receiver, the method doesn't support the receiver if it's not string, file or symbol
+    pub fn text(&self) -> Result<&'v str, MethodError> {
+        self.receiver.as_str().ok_or_else(|| self.unsupported())
+    }
+
+    pub fn unsupported(&self) -> MethodError {
+        MethodError::UnknownMethod { name: self.name.to_string(), receiver: self.receiver.type_name() }
+    }
+
+    pub fn invalid(&self, message: String) -> MethodError {
+        MethodError::InvalidArgument { name: self.name.to_string(), message }
+    }
+
+    fn type_mismatch(&self, index: usize, expected: &'static str, found: &Value) -> MethodError {
+        MethodError::TypeMismatch { name: self.name.to_string(), index, expected, found: found.type_name() }
+    }
+}
+
+pub type Method = Box<dyn Fn(&Invocation) -> Result<Value, MethodError> + Send + Sync>;
+
+/// The methods can be called in expression, for example: `$fileName.split(".").last()`. For regex,
+/// `matches` tells if the text matches and `find` returns the captures of the first match, for example:
+/// `"v1.2".find("v(\\d+)")` is `["v1", "1"]`, or `null` if nothing matches.
+///
+/// Embedders can register their own methods or override the standard ones:
+///
+/// ```rust
+/// use shire_core::methods::MethodRegistry;
+/// use shire_core::runtime::Value;
+///
+/// let mut methods = MethodRegistry::standard();
+/// methods.register("isTest", |call| {
+///     call.arity(0, 0)?;
+///     Ok(Value::Bool(call.text()?.ends_with("Test.kt")))
+/// });
+/// ```
+#[derive(Default)]
+pub struct MethodRegistry {
+    methods: HashMap<String, Method>,
+}
+
+impl MethodRegistry {
+    /// The registry without any method
+    pub fn new() -> Self {
+        MethodRegistry::default()
+    }
+
+    /// The registry with the standard methods of string, list and object
+    pub fn standard() -> Self {
+        let mut methods = MethodRegistry::new();
+        register_standard_methods(&mut methods);
+        methods
+    }
+
+    pub fn register<F>(&mut self, name: &str, method: F)
+    where
+        F: Fn(&Invocation) -> Result<Value, MethodError> + Send + Sync + 'static,
+    {
+        self.methods.insert(name.to_string(), Box::new(method));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.methods.contains_key(name)
+    }
+
+    pub fn call(&self, name: &str, receiver: &Value, args: &[Value]) -> Result<Value, MethodError> {
+        let invocation = Invocation { name, receiver, args };
+        match self.methods.get(name) {
+            Some(method) => method(&invocation),
+            None => Err(invocation.unsupported()),
+        }
+    }
+}
+
+/// The shared registry of standard methods, which is used if the environment doesn't have its own
+pub fn standard_methods() -> &'static MethodRegistry {
+    static METHODS: OnceLock<MethodRegistry> = OnceLock::new();
+    METHODS.get_or_init(MethodRegistry::standard)
+}
+
+/// The number of chars, elements or entries
+fn size(call: &Invocation) -> Result<usize, MethodError> {
+    match call.receiver {
+        Value::List(values) => Ok(values.len()),
+        Value::Object(entries) => Ok(entries.len()),
+        _ => Ok(call.text()?.chars().count()),
+    }
+}
+
+/// The index of element in `len` items, the negative index counts from the end, for example: `-1` is `len - 1`
+fn index(call: &Invocation, index: i64, len: usize) -> Result<usize, MethodError> {
+    match bound(call, index, len)? {
+        resolved if resolved < len => Ok(resolved),
+        _ => Err(call.invalid(format!("index {} is out of range for length {}", index, len))),
+    }
+}
+
+/// The bound of slice in `len` items like [index], which may also be `len` for the end of items
+fn bound(call: &Invocation, index: i64, len: usize) -> Result<usize, MethodError> {
+    let resolved = if index < 0 { len as i64 + index } else { index };
+    match resolved >= 0 && resolved as usize <= len {
+        true => Ok(resolved as usize),
+        false => Err(call.invalid(format!("index {} is out of range for length {}", index, len))),
+    }
+}
+
+fn strings(values: impl Iterator<Item = impl Into<Value>>) -> Value {
+    Value::List(values.map(Into::into).collect())
+}
+
+fn register_standard_methods(methods: &mut MethodRegistry) {
+    // string
+    methods.register("length", |call| {
+        call.arity(0, 0)?;
+        Ok(Value::from(call.text()?.chars().count()))
+    });
+    methods.register("trim", |call| {
+        call.arity(0, 0)?;
+        Ok(Value::from(call.text()?.trim()))
+    });
+    methods.register("lowercase", |call| {
+        call.arity(0, 0)?;
+        Ok(Value::from(call.text()?.to_lowercase()))
+    });
+    methods.register("uppercase", |call| {
+        call.arity(0, 0)?;
+        Ok(Value::from(call.text()?.to_uppercase()))
+    });
+    methods.register("startsWith", |call| {
+        call.arity(1, 1)?;
+        Ok(Value::from(call.text()?.starts_with(call.str_arg(0)?)))
+    });
+    methods.register("endsWith", |call| {
+        call.arity(1, 1)?;
+        Ok(Value::from(call.text()?.ends_with(call.str_arg(0)?)))
+    });
+    methods.register("matches", |call| {
+        call.arity(1, 1)?;
+        Ok(Value::from(call.regex_arg(0)?.is_match(call.text()?)))
+    });
+    // the first match with captures, for example: `"a1b2".find("[a-z](\\d)")` is `["a1", "1"]`
+    methods.register("find", |call| {
+        call.arity(1, 1)?;
+        let captures = call.regex_arg(0)?.captures(call.text()?).map(|captures| {
+            Value::List(captures.iter().map(|group| Value::from(group.map(|it| it.as_str()))).collect())
+        });
+        Ok(Value::from(captures))
+    });
+    methods.register("split", |call| {
+        call.arity(1, 1)?;
+        Ok(strings(call.text()?.split(call.str_arg(0)?)))
+    });
+    methods.register("lines", |call| {
+        call.arity(0, 0)?;
+        Ok(strings(call.text()?.lines()))
+    });
+    methods.register("replace", |call| {
+        call.arity(2, 2)?;
+        Ok(Value::from(call.text()?.replace(call.str_arg(0)?, call.str_arg(1)?)))
+    });
+    // the chars from `start` to `end`, for example: `"Main.kt".substring(0, -3)` is `"Main"`
+    methods.register("substring", |call| {
+        call.arity(1, 2)?;
+        let chars: Vec<char> = call.text()?.chars().collect();
+        let start = bound(call, call.int_arg(0)?, chars.len())?;
+        let end = match call.args.len() {
+            2 => bound(call, call.int_arg(1)?, chars.len())?,
+            _ => chars.len(),
+        };
+        if start > end {
+            return Err(call.invalid(format!("start {} is after end {}", start, end)));
+        }
+        Ok(Value::from(chars[start..end].iter().collect::<String>()))
+    });
+    methods.register("toInt", |call| {
+        call.arity(0, 0)?;
+        match call.receiver {
+            Value::Int(value) => Ok(Value::Int(*value)),
+            // the fraction is dropped, `NaN`, the infinity and the float out of the range of int are errors
+            Value::Float(value) if (i64::MIN as f64..-(i64::MIN as f64)).contains(value) => {
+                Ok(Value::Int(*value as i64))
+            }
+            Value::Float(value) => Err(call.invalid(format!("`{:?}` is not an int", value))),
+            _ => {
+                let text = call.text()?;
+                text.trim().parse::<i64>().map(Value::Int).map_err(|_| call.invalid(format!("`{}` is not an int", text)))
+            }
+        }
+    });
+    // replace `%s` with the arguments in order, for example: `"%s.kt".format($name)`, `%%` is `%`
+    methods.register("format", |call| {
+        let mut args = call.args.iter();
+        let mut formatted = String::new();
+        let mut chars = call.text()?.chars();
+        while let Some(c) = chars.next() {
+            match (c, chars.clone().next()) {
+                ('%', Some('s')) => {
+                    let arg = args.next().ok_or_else(|| call.invalid("missing argument for `%s`".to_string()))?;
+                    formatted.push_str(&arg.to_string());
+                    chars.next();
+                }
+                ('%', Some('%')) => {
+                    formatted.push('%');
+                    chars.next();
+                }
+                (c, _) => formatted.push(c),
+            }
+        }
+        if args.len() > 0 {
+            let index = call.args.len() - args.len() + 1;
+            return Err(call.invalid(format!("argument {} has no `%s` to fill", index)));
+        }
+        Ok(Value::from(formatted))
+    });
+
+    // string, list and object
+    methods.register("size", |call| {
+        call.arity(0, 0)?;
+        Ok(Value::from(size(call)?))
+    });
+    methods.register("isEmpty", |call| {
+        call.arity(0, 0)?;
+        Ok(Value::from(size(call)? == 0))
+    });
+    methods.register("isNotEmpty", |call| {
+        call.arity(0, 0)?;
+        Ok(Value::from(size(call)? != 0))
+    });
+    methods.register("contains", |call| {
+        call.arity(1, 1)?;
+        match call.receiver {
+            Value::List(values) => {
+                let element = call.arg(0)?;
+                Ok(Value::from(values.iter().any(|it| equals(it, element))))
+            }
+            Value::Object(entries) => Ok(Value::from(entries.contains_key(call.str_arg(0)?))),
+            _ => Ok(Value::from(call.text()?.contains(call.str_arg(0)?))),
+        }
+    });
+    // the index of char or element, `-1` if it's not found
+    methods.register("indexOf", |call| {
+        call.arity(1, 1)?;
+        let index = match call.receiver {
+            Value::List(values) => {
+                let element = call.arg(0)?;
+                values.iter().position(|it| equals(it, element))
+            }
+            _ => {
+                let text = call.text()?;
+                text.find(call.str_arg(0)?).map(|index| text[..index].chars().count())
+            }
+        };
+        Ok(Value::Int(index.map(|it| it as i64).unwrap_or(-1)))
+    });
+    methods.register("first", |call| {
+        call.arity(0, 0)?;
+        match call.receiver {
+            Value::List(values) => Ok(Value::from(values.first().cloned())),
+            _ => Ok(Value::from(call.text()?.chars().next().map(String::from))),
+        }
+    });
+    methods.register("last", |call| {
+        call.arity(0, 0)?;
+        match call.receiver {
+            Value::List(values) => Ok(Value::from(values.last().cloned())),
+            _ => Ok(Value::from(call.text()?.chars().last().map(String::from))),
+        }
+    });
+
+    // list and object
+    methods.register("get", |call| {
+        call.arity(1, 1)?;
+        match call.receiver {
+            Value::List(values) => {
+                let index = index(call, call.int_arg(0)?, values.len())?;
+                Ok(Value::from(values.get(index).cloned()))
+            }
+            Value::Object(entries) => Ok(Value::from(entries.get(call.str_arg(0)?).cloned())),
+            _ => Err(call.unsupported()),
+        }
+    });
+    methods.register("join", |call| {
+        call.arity(0, 1)?;
+        let separator = if call.args.is_empty() { ", " } else { call.str_arg(0)? };
+        match call.receiver {
+            Value::List(values) => {
+                Ok(Value::from(values.iter().map(|it| it.to_string()).collect::<Vec<_>>().join(separator)))
+            }
+            _ => Err(call.unsupported()),
+        }
+    });
+    methods.register("keys", |call| {
+        call.arity(0, 0)?;
+        match call.receiver {
+            Value::Object(entries) => Ok(strings(entries.keys().map(String::as_str))),
+            _ => Err(call.unsupported()),
+        }
+    });
+    methods.register("values", |call| {
+        call.arity(0, 0)?;
+        match call.receiver {
+            Value::Object(entries) => Ok(Value::List(entries.values().cloned().collect())),
+            _ => Err(call.unsupported()),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(receiver: impl Into<Value>, name: &str, args: &[Value]) -> Result<Value, MethodError> {
+        standard_methods().call(name, &receiver.into(), args)
+    }
+
+    #[test]
+    fn test_string_methods() {
+        assert_eq!(call("a.b.kt", "split", &[Value::from(".")]), Ok(Value::from(vec!["a", "b", "kt"])));
+        assert_eq!(call("Main.kt", "substring", &[Value::Int(0), Value::Int(-3)]), Ok(Value::from("Main")));
+        assert_eq!(call("Main.kt", "replace", &["kt".into(), "java".into()]), Ok(Value::from("Main.java")));
+        assert_eq!(call("héllo", "indexOf", &["l".into()]), Ok(Value::Int(2)));
+        assert_eq!(call("a1b2", "find", &[r"[a-z](\d)".into()]), Ok(Value::from(vec!["a1", "1"])));
+        assert_eq!(call(" 42 ", "toInt", &[]), Ok(Value::Int(42)));
+        assert_eq!(call(Value::Float(-2.5), "toInt", &[]), Ok(Value::Int(-2)));
+        assert_eq!(call("%s.%s 100%%", "format", &["Main".into(), "kt".into()]), Ok(Value::from("Main.kt 100%")));
+        assert_eq!(call("", "first", &[]), Ok(Value::Null));
+        assert_eq!(call("a\nb", "lines", &[]), Ok(Value::from(vec!["a", "b"])));
+    }
+
+    #[test]
+    fn test_list_and_object_methods() {
+        let list = Value::from(vec!["a", "b"]);
+        assert_eq!(call(list.clone(), "join", &["/".into()]), Ok(Value::from("a/b")));
+        assert_eq!(call(list.clone(), "size", &[]), Ok(Value::Int(2)));
+        assert_eq!(call(list.clone(), "get", &[Value::Int(-1)]), Ok(Value::from("b")));
+        assert_eq!(call(list, "indexOf", &["c".into()]), Ok(Value::Int(-1)));
+
+        let versions = Value::from(vec!["1.0", "2.0"]);
+        assert_eq!(call(versions.clone(), "contains", &[Value::Int(1)]), Ok(Value::Bool(true)));
+        assert_eq!(call(versions.clone(), "contains", &["1".into()]), Ok(Value::Bool(false)));
+        assert_eq!(call(versions, "indexOf", &[Value::Int(2)]), Ok(Value::Int(1)));
+
+        let entries = [("lang", "kotlin"), ("build", "gradle")].map(|(key, value)| (key.to_string(), value.into()));
+        let object = Value::Object(entries.into());
+        assert_eq!(call(object.clone(), "keys", &[]), Ok(Value::from(vec!["lang", "build"])));
+        assert_eq!(call(object.clone(), "contains", &["lang".into()]), Ok(Value::Bool(true)));
+        assert_eq!(call(object, "get", &["name".into()]), Ok(Value::Null));
+    }
+
+    #[test]
+    fn test_method_errors() {
+        let error = |receiver: Value, name: &str, args: &[Value]| call(receiver, name, args).unwrap_err().to_string();
+        assert_eq!(error("a".into(), "foo", &[]), "unknown method `foo` for string");
+        assert_eq!(error(Value::Int(1), "split", &[".".into()]), "unknown method `split` for int");
+        assert_eq!(error("a".into(), "contains", &[]), "`contains` expects 1 argument, found 0");
+        assert_eq!(error("a".into(), "substring", &[]), "`substring` expects 1 to 2 arguments, found 0");
+        assert_eq!(error("a".into(), "split", &[Value::Int(1)]), "`split` expects string for argument 1, found int");
+        assert_eq!(error("abc".into(), "substring", &[Value::Int(5)]), "`substring`: index 5 is out of range for length 3");
+        assert_eq!(error(vec!["a"].into(), "get", &[Value::Int(1)]), "`get`: index 1 is out of range for length 1");
+        assert_eq!(error("x".into(), "toInt", &[]), "`toInt`: `x` is not an int");
+        assert_eq!(error(Value::Float(1e30), "toInt", &[]), "`toInt`: `1e30` is not an int");
+        assert_eq!(error(Value::Float(f64::NAN), "toInt", &[]), "`toInt`: `NaN` is not an int");
+        assert_eq!(error("a".into(), "matches", &["(".into()]), "`matches`: invalid regex `(`");
+        assert_eq!(error("%s".into(), "format", &["a".into(), "b".into()]), "`format`: argument 2 has no `%s` to fill");
+    }
+
+    #[test]
+    fn test_register_method() {
+        let mut methods = MethodRegistry::new();
+        methods.register("twice", |call| Ok(Value::Int(call.receiver.as_int().ok_or_else(|| call.unsupported())? * 2)));
+        assert_eq!(methods.call("twice", &Value::Int(21), &[]), Ok(Value::Int(42)));
+        assert!(!methods.contains("length"));
+    }
+}
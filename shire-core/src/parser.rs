@@ -1,48 +1,83 @@
-use crate::parser::VariableTransform::{Action, PatternAction};
-use nom::bytes::complete::take_while;
-use nom::character::complete::char;
-use nom::multi::{many0, separated_list0};
+use crate::ast::front_matter_type::{FrontMatterType, ShirePsiQueryStatement};
+use crate::ast::pattern_action_fun::{FunctionArg, PatternActionFunc, VariableElement};
+use crate::ast::regex_pattern::RegexPattern;
+use crate::ast::shire_expression::{CaseKeyValue, ConditionCase, Processor, StatementType};
+use crate::body::{parse_body, BodyNode};
+use crate::lexer::{tokenize_code, TokenKind};
+use crate::cst::{parse_cst, parse_front_matter, SyntaxKind, SyntaxNode};
+use crate::expression::{expression, into_operand};
+use crate::diagnostic::{Diagnostic, DiagnosticCode, Diagnostics, Severity};
+use crate::parser::VariableTransform::PatternAction;
+use crate::span::Span;
+use nom::bytes::complete::{take_until, take_while, take_while1, take_while_m_n};
+use nom::character::complete::{char, line_ending, one_of, space0, space1};
+use nom::multi::{many0, separated_list0, separated_list1};
 use nom::sequence::tuple;
 use nom::{
     branch::alt,
     bytes::complete::{is_not, tag},
     character::complete::{digit1, multispace0, multispace1},
-    combinator::{map, opt},
-    multi::{fold_many0, many1},
-    sequence::{delimited, preceded, separated_pair, terminated},
+    combinator::{cut, eof, map, map_opt, not, opt, peek, recognize, value},
+    error::ErrorKind,
+    multi::many1,
+    sequence::{delimited, pair, preceded, separated_pair, terminated},
     IResult,
 };
+use indexmap::IndexMap;
 use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq)]
-enum Function {
+/// A function call in pipeline, for example: `grep("error.log")`
+type FunctionCall = (String, Vec<FunctionArg>);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum Function {
     Functions(
-        Vec<(String, Vec<String>)>,
+        Vec<(String, Vec<FunctionArg>)>,
     ),
 }
 
-#[derive(Debug, PartialEq)]
-enum VariableTransform {
-    String(String),
-    Integer(i32),
-    PatternAction { pattern: String, command: Function },
+impl Function {
+    /// Lower the function calls into [PatternActionFunc], unknown functions become toolchain functions.
+    pub fn to_pattern_action_funcs(&self) -> Vec<PatternActionFunc> {
+        match self {
+            Function::Functions(functions) => to_pattern_action_funcs(functions.clone()),
+        }
+    }
+}
+
+/// The lifecycle hook, which is a pipeline or a case block on the output, the calls are kept as they are
+/// written, for example: `{ parseCode | saveFile("out.java") }` or `{ case "$output" { ... } }`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum LifecycleHook {
     Action { command: Function },
-    Case {
-        pattern: String,
-        cases: HashMap<String, VariableTransform>,
-        default: Option<Box<VariableTransform>>,
-    },
+    Case { case: ConditionCase },
+}
+
+impl LifecycleHook {
+    /// Lower the hook into [PatternActionFunc], the case block is one [PatternActionFunc::CaseMatch].
+    pub fn to_pattern_action_funcs(&self) -> Vec<PatternActionFunc> {
+        match self {
+            LifecycleHook::Action { command } => command.to_pattern_action_funcs(),
+            LifecycleHook::Case { case } => vec![PatternActionFunc::CaseMatch { case: case.clone() }],
+        }
+    }
 }
 
-#[derive(Debug)]
-pub enum ConfigValue {
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum VariableTransform {
     String(String),
-    Number(i64),
-    Object(Vec<(String, ConfigValue)>),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    PatternAction { pattern: RegexPattern, command: Function },
+    Action { command: Function },
+    Case { pattern: RegexPattern, case: ConditionCase },
+    Query(ShirePsiQueryStatement),
 }
 
-#[derive(Debug, PartialEq)]
-enum InteractionType {
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum InteractionType {
     AppendCursor,
     AppendCursorStream,
     OutputFile,
@@ -59,6 +94,19 @@ trait description {
 }
 
 impl InteractionType {
+    pub fn name(&self) -> &str {
+        match self {
+            InteractionType::AppendCursor => "AppendCursor",
+            InteractionType::AppendCursorStream => "AppendCursorStream",
+            InteractionType::OutputFile => "OutputFile",
+            InteractionType::ReplaceSelection => "ReplaceSelection",
+            InteractionType::ReplaceCurrentFile => "ReplaceCurrentFile",
+            InteractionType::InsertBeforeSelection => "InsertBeforeSelection",
+            InteractionType::RunPanel => "RunPanel",
+            InteractionType::OnPaste => "OnPaste",
+        }
+    }
+
     fn description(&self) -> &str {
         match self {
             InteractionType::AppendCursor => "Append content at the current cursor position",
@@ -72,23 +120,23 @@ impl InteractionType {
         }
     }
 
-    fn from(interaction: &str) -> InteractionType {
+    fn from(interaction: &str) -> Option<InteractionType> {
         match interaction.to_lowercase().as_str() {
-            "appendcursor" => InteractionType::AppendCursor,
-            "appendcursorstream" => InteractionType::AppendCursorStream,
-            "outputfile" => InteractionType::OutputFile,
-            "replaceselection" => InteractionType::ReplaceSelection,
-            "replacecurrentfile" => InteractionType::ReplaceCurrentFile,
-            "insertbeforeselection" => InteractionType::InsertBeforeSelection,
-            "runpanel" => InteractionType::RunPanel,
-            "onpaste" => InteractionType::OnPaste,
-            _ => InteractionType::RunPanel,
+            "appendcursor" => Some(InteractionType::AppendCursor),
+            "appendcursorstream" => Some(InteractionType::AppendCursorStream),
+            "outputfile" => Some(InteractionType::OutputFile),
+            "replaceselection" => Some(InteractionType::ReplaceSelection),
+            "replacecurrentfile" => Some(InteractionType::ReplaceCurrentFile),
+            "insertbeforeselection" => Some(InteractionType::InsertBeforeSelection),
+            "runpanel" => Some(InteractionType::RunPanel),
+            "onpaste" => Some(InteractionType::OnPaste),
+            _ => None,
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
-enum ShireActionLocation {
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum ShireActionLocation {
     ContextMenu,
     IntentionMenu,
     TerminalMenu,
@@ -98,7 +146,7 @@ enum ShireActionLocation {
 }
 
 impl ShireActionLocation {
-    fn location(&self) -> &str {
+    pub fn location(&self) -> &str {
         match self {
             ShireActionLocation::ContextMenu => "ContextMenu",
             ShireActionLocation::IntentionMenu => "IntentionMenu",
@@ -120,15 +168,15 @@ impl ShireActionLocation {
         }
     }
 
-    fn from(action_location: &str) -> ShireActionLocation {
+    fn from(action_location: &str) -> Option<ShireActionLocation> {
         match action_location {
-            "ContextMenu" => ShireActionLocation::ContextMenu,
-            "IntentionMenu" => ShireActionLocation::IntentionMenu,
-            "TerminalMenu" => ShireActionLocation::TerminalMenu,
-            "CommitMenu" => ShireActionLocation::CommitMenu,
-            "RunPanel" => ShireActionLocation::RunPanel,
-            "InputBox" => ShireActionLocation::InputBox,
-            _ => ShireActionLocation::RunPanel,
+            "ContextMenu" => Some(ShireActionLocation::ContextMenu),
+            "IntentionMenu" => Some(ShireActionLocation::IntentionMenu),
+            "TerminalMenu" => Some(ShireActionLocation::TerminalMenu),
+            "CommitMenu" => Some(ShireActionLocation::CommitMenu),
+            "RunPanel" => Some(ShireActionLocation::RunPanel),
+            "InputBox" => Some(ShireActionLocation::InputBox),
+            _ => None,
         }
     }
 
@@ -148,13 +196,37 @@ impl ShireActionLocation {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct HobbitHole {
-    name: String,
-    description: Option<String>,
-    interaction: Option<InteractionType>,
-    action_location: Option<ShireActionLocation>,
-    variables: HashMap<String, VariableTransform>,
+    pub name: String,
+    pub description: Option<String>,
+    pub interaction: Option<InteractionType>,
+    pub action_location: Option<ShireActionLocation>,
+    /// The variables in declaration order, which is also the order to evaluate them
+    pub variables: IndexMap<String, VariableTransform>,
+    /// The condition for the action to be available, for example:
+    /// `when: $fileName.contains(".java") && $filePath.endsWith("Test.java")`
+    pub when: Option<StatementType>,
+    /// Lifecycle hooks run around the streaming of model output, for example:
+    /// `afterStreaming: { parseCode | saveFile("out.java") }`
+    pub before_streaming: Option<LifecycleHook>,
+    pub on_streaming: Option<LifecycleHook>,
+    pub after_streaming: Option<LifecycleHook>,
+    pub on_streaming_end: Option<LifecycleHook>,
+    /// Model settings for the script, which can be a name or a nested object, for example:
+    /// ```shire
+    /// model:
+    ///   provider: "openai"
+    ///   temperature: 0
+    /// ```
+    pub model: Option<FrontMatterType>,
+    /// The other keys in declaration order, which keep the structured configuration of script, for example:
+    /// ```shire
+    /// config:
+    ///   retries: 3
+    /// ```
+    #[serde(default)]
+    pub user_data: IndexMap<String, FrontMatterType>,
 }
 
 impl Default for HobbitHole {
@@ -164,7 +236,14 @@ impl Default for HobbitHole {
             description: None,
             interaction: None,
             action_location: None,
-            variables: HashMap::new(),
+            variables: IndexMap::new(),
+            when: None,
+            before_streaming: None,
+            on_streaming: None,
+            after_streaming: None,
+            on_streaming_end: None,
+            model: None,
+            user_data: IndexMap::new(),
         }
     }
 }
@@ -176,6 +255,31 @@ pub enum HobbitHoleKey {
     Interaction,
     ActionLocation,
     Variables,
+    When,
+    BeforeStreaming,
+    OnStreaming,
+    AfterStreaming,
+    OnStreamingEnd,
+    Model,
+    Unknown(String),
+}
+
+impl HobbitHoleKey {
+    /// The description of expected value, used in the diagnostic of invalid value
+    fn expected(&self) -> &str {
+        match self {
+            HobbitHoleKey::Name => "a quoted string like `\"Summary\"`",
+            HobbitHoleKey::Description | HobbitHoleKey::Interaction | HobbitHoleKey::ActionLocation => "a text",
+            HobbitHoleKey::Variables => "variables like `\"var1\": \"demo\"`",
+            HobbitHoleKey::When => "a condition like `$fileName.contains(\".java\")`",
+            HobbitHoleKey::BeforeStreaming
+            | HobbitHoleKey::OnStreaming
+            | HobbitHoleKey::AfterStreaming
+            | HobbitHoleKey::OnStreamingEnd => "actions like `{ parseCode | saveFile(\"out.java\") }`",
+            HobbitHoleKey::Model => "a value or a nested object",
+            HobbitHoleKey::Unknown(_) => "a value",
+        }
+    }
 }
 
 impl From<&str> for HobbitHoleKey {
@@ -186,66 +290,246 @@ impl From<&str> for HobbitHoleKey {
             "interaction" => HobbitHoleKey::Interaction,
             "actionLocation" => HobbitHoleKey::ActionLocation,
             "variables" => HobbitHoleKey::Variables,
-            _ => HobbitHoleKey::Name,
+            "when" => HobbitHoleKey::When,
+            "beforeStreaming" => HobbitHoleKey::BeforeStreaming,
+            "onStreaming" => HobbitHoleKey::OnStreaming,
+            "afterStreaming" => HobbitHoleKey::AfterStreaming,
+            "onStreamingEnd" => HobbitHoleKey::OnStreamingEnd,
+            "model" => HobbitHoleKey::Model,
+            _ => HobbitHoleKey::Unknown(key.to_string()),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
-struct ShireFile {
-    hobbit: HobbitHole,
-    body: Vec<String>, // This represents the body where `$var1` is located.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ShireFile {
+    pub hobbit: HobbitHole,
+    pub body: Vec<BodyNode>,
+    /// The comment lines in front matter, the comments in body are kept in [ShireFile::body]
+    pub comments: Vec<Comment>,
+}
+
+/// The line comment in front matter, for example: `# TODO` or `// the model for review`,
+/// it's not part of [HobbitHole] but kept as trivia for formatters.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Comment {
+    /// The comment with its `#` or `//` marker, without the line break
+    pub text: String,
+    pub span: Span,
 }
 
 fn parse_string(input: &str) -> IResult<&str, String> {
     map(is_not("|\n"), |s: &str| s.to_string())(input)
 }
 
-fn parse_quoted_string(input: &str) -> IResult<&str, String> {
-    delimited(
-        char('"'),                             // opening quote
-        map(is_not("\""), |s: &str| s.to_string()), // content of the string
-        char('"'),                             // closing quote
-    )(input)
+/// Parser for unicode escape after `\u`, for example: `\u{1F600}` or `\u0041`
+fn parse_unicode_escape(input: &str) -> IResult<&str, char> {
+    let is_hex = |c: char| c.is_ascii_hexdigit();
+    cut(map_opt(
+        alt((delimited(char('{'), take_while_m_n(1, 6, is_hex), char('}')), take_while_m_n(4, 4, is_hex))),
+        |hex: &str| u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+    ))(input)
+}
+
+/// Parser for string in one line quoted by `quote`, unknown escapes like `\d` are kept as they are,
+/// so that the regex in arguments still works.
+fn parse_escaped_string(quote: char) -> impl Fn(&str) -> IResult<&str, String> {
+    move |input: &str| {
+        let (mut input, _) = char(quote)(input)?;
+        let mut value = String::new();
+        loop {
+            let mut chars = input.chars();
+            match chars.next() {
+                Some(c) if c == quote => return Ok((chars.as_str(), value)),
+                Some('\\') => {
+                    let escaped = match chars.next() {
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('0') => '\0',
+                        Some('u') => {
+                            let (rest, unicode) = parse_unicode_escape(chars.as_str())?;
+                            chars = rest.chars();
+                            unicode
+                        }
+                        Some(c) if c == '\\' || c == '"' || c == '\'' => c,
+                        Some(c) if c != '\n' => {
+                            value.push('\\');
+                            c
+                        }
+                        _ => return Err(nom::Err::Error(nom::error::Error::new(input, ErrorKind::Char))),
+                    };
+                    value.push(escaped);
+                }
+                Some(c) if c != '\n' => value.push(c),
+                _ => return Err(nom::Err::Error(nom::error::Error::new(input, ErrorKind::Char))),
+            }
+            input = chars.as_str();
+        }
+    }
+}
+
+/// Parser for multi-line string, the content is kept as it is without escapes,
+/// and the line break right after the opening quotes is dropped.
+fn parse_triple_quoted_string(input: &str) -> IResult<&str, String> {
+    let (input, content) = delimited(tag("\"\"\""), take_until("\"\"\""), tag("\"\"\""))(input)?;
+    let content = content.strip_prefix("\r\n").or_else(|| content.strip_prefix('\n')).unwrap_or(content);
+    Ok((input, content.to_string()))
+}
+
+/// Parser for string literal, for example: `"say \"hi\"\n"`, `'single'`, `""` or `"""multi-line"""`
+pub(crate) fn parse_quoted_string(input: &str) -> IResult<&str, String> {
+    alt((parse_triple_quoted_string, parse_escaped_string('"'), parse_escaped_string('\'')))(input)
+}
+
+/// Recognizer for number literal, for example: `42`, `-1`, `0.75` or `1e-3`
+pub(crate) fn number_literal(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        opt(char('-')),
+        digit1,
+        opt(pair(char('.'), digit1)),
+        opt(tuple((one_of("eE"), opt(one_of("+-")), digit1))),
+    )))(input)
+}
+
+/// The literal with fraction or exponent is float, others are 64 bits integer,
+/// returns `None` if the number is out of range.
+fn to_number(literal: &str) -> Option<FrontMatterType> {
+    match literal.contains(['.', 'e', 'E']) {
+        true => literal.parse::<f64>().ok().filter(|it| it.is_finite()).map(FrontMatterType::FLOAT),
+        false => literal.parse::<i64>().ok().map(FrontMatterType::NUMBER),
+    }
+}
+
+/// Parser for number literal, the overflow fails with [ErrorKind::TooLarge] instead of trying other parsers,
+/// so that it can be reported by [number_overflow].
+pub(crate) fn parse_number(input: &str) -> IResult<&str, FrontMatterType> {
+    let (rest, literal) = number_literal(input)?;
+    match to_number(literal) {
+        Some(number) => Ok((rest, number)),
+        None => Err(nom::Err::Failure(nom::error::Error::new(input, ErrorKind::TooLarge))),
+    }
+}
+
+/// Recognizer for regex literal, returns the pattern with `\/` unescaped and the flags after the closing slash
+pub(crate) fn regex_literal(input: &str) -> IResult<&str, (String, &str)> {
+    let (mut input, _) = char('/')(input)?;
+    let mut pattern = String::new();
+    loop {
+        let mut chars = input.chars();
+        match chars.next() {
+            Some('/') if !pattern.is_empty() => break,
+            Some('\\') => match chars.next() {
+                Some('/') => pattern.push('/'),
+                Some(c) if c != '\n' => {
+                    pattern.push('\\');
+                    pattern.push(c);
+                }
+                _ => return Err(nom::Err::Error(nom::error::Error::new(input, ErrorKind::Escaped))),
+            },
+            Some(c) if c != '/' && c != '\n' => pattern.push(c),
+            _ => return Err(nom::Err::Error(nom::error::Error::new(input, ErrorKind::Char))),
+        }
+        input = chars.as_str();
+    }
+
+    let (input, flags) = preceded(char('/'), take_while(|c: char| c.is_ascii_alphabetic()))(input)?;
+    Ok((input, (pattern, flags)))
+}
+
+/// Parser for regex literal, for example: `/src\/main\/.*\.java/` or `/error/i`,
+/// the invalid regex fails with [ErrorKind::RegexpMatch] so that it can be reported by [literal_error].
+fn parse_regex_literal(input: &str) -> IResult<&str, RegexPattern> {
+    let (rest, (pattern, flags)) = regex_literal(input)?;
+    match RegexPattern::new(&pattern, flags) {
+        Ok(pattern) => Ok((rest, pattern)),
+        Err(_) => Err(nom::Err::Failure(nom::error::Error::new(input, ErrorKind::RegexpMatch))),
+    }
 }
 
-fn parse_function(input: &str) -> IResult<&str, (String, Vec<String>)> {
-    let (input, cmd) = take_while(|c: char| c.is_alphanumeric())(input)?;
+/// The diagnostic of malformed literal in `err`, which is the number overflow or invalid regex,
+/// returns `None` for other errors.
+pub(crate) fn literal_error<'a>(err: &nom::Err<nom::error::Error<&'a str>>) -> Option<(DiagnosticCode, String, &'a str)> {
+    match err {
+        nom::Err::Failure(error) if error.code == ErrorKind::TooLarge => {
+            let literal = number_literal(error.input).map(|(_, literal)| literal).unwrap_or(error.input);
+            Some((DiagnosticCode::NumberOverflow, format!("number `{}` is out of range", literal), literal))
+        }
+        nom::Err::Failure(error) if error.code == ErrorKind::RegexpMatch => {
+            let (rest, (pattern, flags)) = regex_literal(error.input).ok()?;
+            let message = RegexPattern::new(&pattern, flags).err()?;
+            Some((DiagnosticCode::InvalidRegex, message, &error.input[..error.input.len() - rest.len()]))
+        }
+        _ => None,
+    }
+}
+
+fn report_literal_error<'a>(err: &nom::Err<nom::error::Error<&'a str>>, diagnostics: &mut Diagnostics<'a>) -> bool {
+    match literal_error(err) {
+        Some((code, message, literal)) => {
+            diagnostics.report(Severity::Error, code, message, diagnostics.span_of(literal));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Parser for function call in pipeline, for example: `grep("a", "b")`, `head(n=5)` or `sed($pattern, "x")`
+fn parse_function<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, FunctionCall> {
+    let (input, cmd) = take_while1(|c: char| c.is_alphanumeric() || c == '_')(input)?;
     let (input, args) = opt(preceded(
         multispace0,
         delimited(
-            char('('),
-            separated_list0(char(','), delimited(char('"'), is_not("\""), char('"'))),
-            char(')'),
+            pair(char('('), multispace0),
+            separated_list0(delimited(multispace0, char(','), multispace0), |input| {
+                parse_function_arg(source, input)
+            }),
+            pair(multispace0, char(')')),
         ),
     ))(input)?;
 
-    let opt_args: Vec<String> = match args {
-        Some(args) => args.into_iter().map(|s| s.to_string()).collect(),
-        None => vec![],
-    };
+    Ok((input, (cmd.to_string(), args.unwrap_or_default())))
+}
+
+/// Parser for the argument of function, which is a nested call like `trim($name)` or an expression
+/// like `"error"`, `0.75`, `true` or `$name.length()`, optionally named like `n=5`.
+fn parse_function_arg<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, FunctionArg> {
+    let (input, name) = opt(terminated(
+        parse_identifier,
+        tuple((multispace0, char('='), not(char('=')), multispace0)),
+    ))(input)?;
+    let (input, value) = alt((
+        map(
+            preceded(peek(tuple((parse_identifier, multispace0, char('(')))), |input| parse_function(source, input)),
+            |(name, args)| {
+                let call = PatternActionFunc::from_args(&name, args);
+                FrontMatterType::EXPRESSION(StatementType::Processor(Processor::new(vec![call])))
+            },
+        ),
+        map(expression(source), into_operand),
+    ))(input)?;
 
-    Ok((input, (cmd.to_string(), opt_args)))
+    Ok((input, FunctionArg { name, value }))
 }
 
 /// Parser for pattern action
 /// for example: `/.*.java/ { grep("error.log") | sort | xargs("rm") }`
-fn parse_pattern_actions(input: &str) -> IResult<&str, VariableTransform> {
-    let (input, pattern) = delimited(tag("/"), is_not("/"), tag("/"))(input)?;
-    let (input, functions) = parse_actions(input)?;
+fn parse_pattern_actions<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, VariableTransform> {
+    let (input, pattern) = parse_regex_literal(input)?;
+    let (input, functions) = parse_actions(source, input)?;
 
     Ok((input, VariableTransform::PatternAction {
-        pattern: pattern.to_string(),
+        pattern,
         command: Function::Functions(functions),
     }))
 }
 
-fn parse_actions(input: &str) -> IResult<&str, Vec<(String, Vec<String>)>> {
+fn parse_actions<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, Vec<FunctionCall>> {
     let (input, functions) = delimited(
         tuple((multispace0, tag("{"), multispace0)),
         separated_list0(
             delimited(multispace0, tag("|"), multispace0),
-            parse_function,
+            |input| parse_function(source, input),
         ),
         tuple((multispace0, tag("}"), multispace0)),
     )(input)?;
@@ -253,13 +537,65 @@ fn parse_actions(input: &str) -> IResult<&str, Vec<(String, Vec<String>)>> {
     Ok((input, functions))
 }
 
-fn parse_case_block_actions(input: &str) -> IResult<&str, (String, VariableTransform)> {
-    let (input, case) = parse_quoted_string(input)?;
-    let (input, functions) = parse_actions(input)?;
+/// Parser for the key of case arm, which is glob like `"*.java"`, regex like `/.*\.kts?/i` or `default`
+fn parse_case_key(input: &str) -> IResult<&str, FrontMatterType> {
+    alt((
+        map(parse_quoted_string, FrontMatterType::STRING),
+        map(parse_regex_literal, FrontMatterType::REGEX),
+        map(tag("default"), |name: &str| FrontMatterType::IDENTIFIER(name.to_string())),
+    ))(input)
+}
+
+/// Parser for the arm of case block, the value is a pipeline or a nested case block
+/// for example: `"*.java" { grep("class") | sort }` or `default { case "$0" { ... } }`
+fn parse_case_arm<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, CaseKeyValue> {
+    let (input, key) = parse_case_key(input)?;
+    let (input, value) = alt((
+        map(
+            delimited(
+                tuple((multispace0, char('{'), multispace0)),
+                |input| parse_condition_case(source, input),
+                tuple((multispace0, char('}'), multispace0)),
+            ),
+            StatementType::ConditionCase,
+        ),
+        map(
+            |input| parse_actions(source, input),
+            |functions| StatementType::Processor(Processor::new(to_pattern_action_funcs(functions))),
+        ),
+    ))(input)?;
 
-    Ok((input, (case, Action {
-        command: Function::Functions(functions),
-    })))
+    Ok((input, CaseKeyValue::new(key, FrontMatterType::EXPRESSION(value))))
+}
+
+/// Parser for case block on the subject, which is a variable or a quoted string,
+/// the arms are matched in order and `default` is optional.
+/// for example: `case "$fileName" { "*.java" { grep("class") } /.*\.kt/ { grep("fun") } }`
+fn parse_condition_case<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, ConditionCase> {
+    let (input, subject) = preceded(
+        pair(tag("case"), multispace1),
+        alt((
+            map(parse_quoted_string, FrontMatterType::STRING),
+            map(preceded(char('$'), parse_identifier), FrontMatterType::VARIABLE),
+        )),
+    )(input)?;
+    let (input, cases) = delimited(
+        tuple((multispace0, char('{'), multispace0)),
+        many0(terminated(|input| parse_case_arm(source, input), multispace0)),
+        char('}'),
+    )(input)?;
+
+    Ok((input, ConditionCase::new(subject, cases)))
+}
+
+/// Parser for the case block wrapped in braces
+/// for example: `{ case "$0" { "error" { grep("ERROR") } default { grep("ERROR") } } }`
+fn parse_case_match<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, ConditionCase> {
+    delimited(
+        tuple((multispace0, char('{'), multispace0)),
+        |input| parse_condition_case(source, input),
+        tuple((multispace0, char('}'), multispace0)),
+    )(input)
 }
 
 /// ```shire
@@ -275,158 +611,738 @@ fn parse_case_block_actions(input: &str) -> IResult<&str, (String, VariableTrans
 ///   }
 /// ---
 /// ```
-fn parse_case_block(input: &str) -> IResult<&str, VariableTransform> {
-    let (input, pattern) = delimited(tag("/"), is_not("/"), tag("/"))(input)?;
-    let (input, _) = delimited(multispace0, tag("{"), multispace0)(input)?;
-
-    /// case syntax
-    let (input, _) = delimited(
-        tag("case"),
-        tuple((multispace1, parse_quoted_string, multispace1)),
-        tuple((multispace0, tag("{"), multispace0)),
-    )(input)?;
+fn parse_case_block<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, VariableTransform> {
+    let (input, pattern) = parse_regex_literal(input)?;
+    let (input, case) = parse_case_match(source, input)?;
 
-    let (input, cases)= many0(
-        parse_case_block_actions,
-    )(input)?;
+    Ok((input, VariableTransform::Case { pattern, case }))
+}
 
-    // parse default case
-    let (input, default) = delimited(
-        tuple((multispace0, tag("default"), multispace1)),
-        parse_actions,
-        tuple((multispace0, tag("}"), multispace0)),
-    )(input)?;
+fn to_pattern_action_funcs(functions: Vec<FunctionCall>) -> Vec<PatternActionFunc> {
+    functions
+        .into_iter()
+        .map(|(name, args)| PatternActionFunc::from_args(&name, args))
+        .collect()
+}
 
-    let mut cases: HashMap<String, VariableTransform> = cases.into_iter().collect();
-    cases.insert("default".to_string(), Action {
-        command: Function::Functions(default.clone()),
-    });
+/// Check the pipeline functions in `source` of a variable or hook with [PatternActionFunc::try_from_name],
+/// the misuse like `head("ten")` is reported at the function name.
+fn check_functions<'a>(source: &'a str, diagnostics: &mut Diagnostics<'a>) {
+    // functions only start after `{` or `|`, the strings and regex like `/a{2}|b/` are single tokens
+    let tokens = tokenize_code(source);
+    for (index, token) in tokens.iter().enumerate() {
+        if !matches!(token.kind, TokenKind::LeftBrace | TokenKind::Pipe) {
+            continue;
+        }
 
-    let (input, _) = delimited(multispace0, tag("}"), multispace0)(input)?;
+        let Some(name) = tokens[index + 1..].iter().find(|it| !it.kind.is_trivia()) else {
+            continue;
+        };
+        if name.kind != TokenKind::Identifier || name.text == "case" || name.text == "default" {
+            continue;
+        }
 
-    Ok((input, VariableTransform::Case {
-        pattern: pattern.to_string(),
-        cases,
-        default: Some(Box::new(Action {
-            command: Function::Functions(default.clone()),
-        })),
-    }))
+        let Ok((_, (function, args))) = parse_function(diagnostics.source(), &source[name.span.start..]) else {
+            continue;
+        };
+        if let Err(message) = PatternActionFunc::try_from_args(&function, args) {
+            diagnostics.report(Severity::Error, DiagnosticCode::InvalidArgument, message, diagnostics.span_of(name.text));
+        }
+    }
 }
 
-fn parse_integer(input: &str) -> IResult<&str, i32> {
-    let (input, digits) = digit1(input)?;
-    let value = digits.parse::<i32>().unwrap();
-    Ok((input, value))
+/// Parser for lifecycle hook, which is a pipeline or a case block on the output
+/// for example: `{ parseCode | saveFile("out.java") }`
+fn parse_lifecycle_hook<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, LifecycleHook> {
+    alt((
+        map(|input| parse_case_match(source, input), |case| LifecycleHook::Case { case }),
+        map(|input| parse_actions(source, input), |functions| LifecycleHook::Action {
+            command: Function::Functions(functions),
+        }),
+    ))(input)
 }
 
-fn parse_variable_value(input: &str) -> IResult<&str, VariableTransform> {
+fn parse_variable_value<'a>(source: &'a str) -> impl FnMut(&'a str) -> IResult<&'a str, VariableTransform> {
     alt((
-        parse_pattern_actions,
-        parse_case_block,
+        move |input| parse_pattern_actions(source, input),
+        move |input| parse_case_block(source, input),
+        map(move |input| parse_query_statement(source, input), VariableTransform::Query),
         map(parse_quoted_string, VariableTransform::String),
-        map(parse_integer, VariableTransform::Integer),
-    ))(input)
+        map_opt(parse_number, |number| match number {
+            FrontMatterType::NUMBER(value) => Some(VariableTransform::Integer(value)),
+            FrontMatterType::FLOAT(value) => Some(VariableTransform::Float(value)),
+            _ => None,
+        }),
+        map(tag("true"), |_| VariableTransform::Boolean(true)),
+        map(tag("false"), |_| VariableTransform::Boolean(false)),
+    ))
 }
 
 ///
 /// parse for key value pair value
 /// for example: `"var1": "demo"`
 ///
-fn parse_variable(input: &str) -> IResult<&str, (String, VariableTransform)> {
+fn parse_variable<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, (String, VariableTransform)> {
     let (input, (key, value)) = tuple((
-        preceded(multispace0, delimited(tag("\""), is_not("\""), tag("\""))),
+        preceded(multispace0, parse_quoted_string),
         preceded(
             delimited(multispace0, tag(":"), multispace0),
-            parse_variable_value,
+            parse_variable_value(source),
         ),
     ))(input)?;
 
-    Ok((input, (key.to_string(), value)))
+    Ok((input, (key, value)))
+}
+
+fn parse_identifier(input: &str) -> IResult<&str, String> {
+    map(
+        take_while1(|c: char| c.is_alphanumeric() || c == '_'),
+        |s: &str| s.to_string(),
+    )(input)
 }
 
-// 前置的 `---` 标记
-fn parse_frontmatter_start(input: &str) -> IResult<&str, ()> {
-    let (input, _) = multispace0(input)?;
-    let (input, _) = tag("---")(input)?;
-    let (input, _) = multispace0(input)?;
-    Ok((input, ()))
+/// Parser for the element declared in `from`, for example: `PsiClass clazz`
+fn parse_variable_element(input: &str) -> IResult<&str, VariableElement> {
+    map(separated_pair(parse_identifier, space1, parse_identifier), |(r#type, name)| {
+        VariableElement::new(r#type, name)
+    })(input)
 }
 
-fn parse_frontmatter_end(input: &str) -> IResult<&str, ()> {
-    let (input, _) = multispace0(input)?;
-    let (input, _) = tag("---")(input)?;
-    let (input, _) = multispace0(input)?;
-    Ok((input, ()))
+/// The items in query clause are separated by comma or new line
+fn parse_query_separator(input: &str) -> IResult<&str, ()> {
+    value((), tuple((space0, alt((tag(","), line_ending)), multispace0)))(input)
 }
 
-fn parse_hobbit_hole(input: &str) -> IResult<&str, HobbitHole> {
-    let (mut cond_input, _) = parse_frontmatter_start(input)?;
-    let mut hole = HobbitHole::default();
-    while !cond_input.is_empty() {
-        let (input, key) = take_while(|c: char| c.is_alphanumeric())(cond_input)?;
-        let (value_input, _) = delimited(multispace0, tag(":"), multispace0)(input)?;
-        cond_input = value_input;
-
-        match HobbitHoleKey::from(key) {
-            HobbitHoleKey::Name => {
-                let (new, name) = parse_quoted_string(value_input)?;
-                hole.name = name;
-                cond_input = new
+fn parse_query_clause<'a, O>(
+    keyword: &'static str,
+    content: impl FnMut(&'a str) -> IResult<&'a str, O>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, O> {
+    delimited(
+        tuple((multispace0, tag(keyword), multispace0, char('{'), multispace0)),
+        content,
+        tuple((multispace0, char('}'))),
+    )
+}
+
+/// Parser for PSI query of code elements
+/// for example: `{ from { PsiClass clazz } where { clazz.getName() == "Foo" } select { clazz.id, clazz.name } }`
+fn parse_query_statement<'a>(source: &'a str, input: &'a str) -> IResult<&'a str, ShirePsiQueryStatement> {
+    let (input, _) = char('{')(input)?;
+    let (input, from) = parse_query_clause("from", separated_list1(parse_query_separator, parse_variable_element))(input)?;
+    let (input, where_clause) = parse_query_clause("where", expression(source))(input)?;
+    let (input, select) =
+        parse_query_clause("select", separated_list1(parse_query_separator, expression(source)))(input)?;
+    let (input, _) = tuple((multispace0, char('}')))(input)?;
+
+    Ok((input, ShirePsiQueryStatement::new(from, where_clause, select)))
+}
+
+fn parse_line_end(input: &str) -> IResult<&str, ()> {
+    value((), tuple((space0, alt((line_ending, eof)))))(input)
+}
+
+fn indentation(input: &str) -> usize {
+    input.len() - input.trim_start_matches(' ').len()
+}
+
+/// Skip the blank lines, returns the input at the start of next line with content
+fn skip_blank_lines(input: &str) -> &str {
+    let mut input = input;
+    while let Some(end) = input.find('\n') {
+        let line = input[..end].trim();
+        if !line.is_empty() {
+            break;
+        }
+        input = &input[end + 1..];
+    }
+
+    input
+}
+
+/// Plain scalar without quotes, for example: `openai`, `-1`, `0.75` or `true`,
+/// the text is number only if it's a number literal as a whole, so `3.5-turbo` is still a string.
+fn plain_scalar(text: &str) -> Result<FrontMatterType, nom::Err<nom::error::Error<&str>>> {
+    match text {
+        "true" => Ok(FrontMatterType::BOOLEAN(true)),
+        "false" => Ok(FrontMatterType::BOOLEAN(false)),
+        _ => match number_literal(text) {
+            Ok(("", literal)) => to_number(literal)
+                .ok_or_else(|| nom::Err::Failure(nom::error::Error::new(text, ErrorKind::TooLarge))),
+            _ => Ok(FrontMatterType::STRING(text.to_string())),
+        },
+    }
+}
+
+/// Parser for scalar or flow array, plain scalar ends before any char of `terminators`
+fn parse_scalar<'a>(terminators: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, FrontMatterType> {
+    move |input: &'a str| {
+        alt((
+            map(parse_quoted_string, FrontMatterType::STRING),
+            parse_flow_array,
+            |input: &'a str| {
+                let (rest, text) = is_not(terminators)(input)?;
+                plain_scalar(text.trim()).map(|value| (rest, value))
+            },
+        ))(input)
+    }
+}
+
+/// Parser for array in one line, for example: `["gpt-4", "gpt-3.5"]`
+fn parse_flow_array(input: &str) -> IResult<&str, FrontMatterType> {
+    map(
+        delimited(
+            tuple((char('['), space0)),
+            separated_list0(delimited(space0, char(','), space0), parse_scalar(",]\r\n")),
+            tuple((space0, char(']'))),
+        ),
+        FrontMatterType::ARRAY,
+    )(input)
+}
+
+/// Parser for the key of object or front matter, which is quoted or like `max_tokens` and `top-p`
+pub(crate) fn parse_object_key(input: &str) -> IResult<&str, String> {
+    alt((
+        parse_quoted_string,
+        map(take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-'), |s: &str| s.to_string()),
+    ))(input)
+}
+
+/// Parser for `key: value` entry of object which is indented by `indent` spaces
+fn parse_object_entry<'a>(indent: usize) -> impl Fn(&'a str) -> IResult<&'a str, (String, FrontMatterType)> {
+    move |input: &'a str| {
+        let (input, key) = parse_object_key(input)?;
+        let (input, _) = tuple((space0, char(':'), peek(alt((space1, line_ending, eof)))))(input)?;
+        let (input, _) = space0(input)?;
+        let (input, value) = parse_front_matter_value(indent)(input)?;
+        Ok((input, (key, value)))
+    }
+}
+
+/// Parser for the lines of object which are indented by `indent` spaces, the first entry may have been
+/// parsed already, for example: the compact `- name: "a"` array item.
+fn parse_block_object<'a>(
+    indent: usize,
+    mut object: IndexMap<String, FrontMatterType>,
+) -> impl FnOnce(&'a str) -> IResult<&'a str, FrontMatterType> {
+    move |mut input: &'a str| {
+        loop {
+            let line = skip_blank_lines(input);
+            if line.is_empty() || indentation(line) != indent {
+                break;
+            }
+
+            let (rest, (key, value)) = parse_object_entry(indent)(&line[indent..])?;
+            object.insert(key, value);
+            input = rest;
+        }
+
+        Ok((input, FrontMatterType::OBJECT(object)))
+    }
+}
+
+/// Parser for the `- item` lines of array which are indented by `indent` spaces
+fn parse_block_array(indent: usize) -> impl Fn(&str) -> IResult<&str, FrontMatterType> {
+    move |mut input: &str| {
+        let mut items = vec![];
+        loop {
+            let line = skip_blank_lines(input);
+            if indentation(line) != indent || !line[indent..].starts_with('-') {
+                break;
+            }
+
+            let (rest, _) = tuple((char('-'), space0))(&line[indent..])?;
+            let item_indent = line.len() - rest.len();
+            let (rest, item) = match parse_object_entry(item_indent)(rest) {
+                Ok((rest, (key, value))) => {
+                    let object = IndexMap::from([(key, value)]);
+                    parse_block_object(item_indent, object)(rest)?
+                }
+                Err(_) => parse_front_matter_value(indent)(rest)?,
+            };
+
+            items.push(item);
+            input = rest;
+        }
+
+        Ok((input, FrontMatterType::ARRAY(items)))
+    }
+}
+
+/// Parser for value in front matter, which is a scalar in the same line, or a nested object/array
+/// in the following lines which are indented deeper than `indent`.
+///
+/// ```shire
+/// model:
+///   provider: "openai"
+///   stop:
+///     - "\n\n"
+///   tools: ["grep", "sed"]
+/// ```
+fn parse_front_matter_value<'a>(indent: usize) -> impl Fn(&'a str) -> IResult<&'a str, FrontMatterType> {
+    move |input: &'a str| {
+        if let Ok((rest, _)) = parse_line_end(input) {
+            let line = skip_blank_lines(rest);
+            let child_indent = indentation(line);
+            if child_indent <= indent || line[child_indent..].is_empty() {
+                return Ok((rest, FrontMatterType::EMPTY));
             }
-            HobbitHoleKey::Description => {
-                let (new, description) = parse_string(value_input)?;
-                hole.description = Some(description);
-                cond_input = new;
+
+            return if line[child_indent..].starts_with('-') {
+                parse_block_array(child_indent)(line)
+            } else {
+                parse_block_object(child_indent, IndexMap::new())(line)
+            };
+        }
+
+        let (input, value) = parse_scalar("\r\n")(input)?;
+        let (input, _) = parse_line_end(input)?;
+        Ok((input, value))
+    }
+}
+
+/// Skip the whitespaces before the next key or variable
+fn skip_trivia(input: &str) -> &str {
+    input.trim_start_matches([' ', '\t', '\r', '\n'])
+}
+
+/// The comments in front matter of syntax tree
+fn collect_comments(front_matter: &SyntaxNode) -> Vec<Comment> {
+    front_matter
+        .tokens()
+        .into_iter()
+        .filter(|token| token.kind == SyntaxKind::Comment)
+        .map(|token| Comment { text: token.text.clone(), span: token.span })
+        .collect()
+}
+
+/// The source with the comments of front matter replaced by spaces, so the AST is parsed without them
+/// wherever they are, and the spans and line/column of diagnostics are still the same as in source.
+fn mask_comments(source: &str, front_matter: Option<&SyntaxNode>) -> String {
+    let mut code = source.to_string();
+    for token in front_matter.map(|it| it.tokens()).unwrap_or_default() {
+        if token.kind == SyntaxKind::Comment {
+            code.replace_range(token.span.start..token.span.end, &" ".repeat(token.span.len()));
+        }
+    }
+
+    code
+}
+
+fn parse_hobbit_hole(input: &str) -> IResult<&str, HobbitHole> {
+    let front_matter = parse_front_matter(input, 0);
+    let code = mask_comments(input, front_matter.as_ref());
+    // the rest of masked code is mapped back to `input` at the same position
+    let rest = |remaining: &str| &input[code.len() - remaining.len()..];
+    match hobbit_hole_from(front_matter.as_ref(), &code, &mut Diagnostics::new(&code)) {
+        Ok((remaining, hole)) => Ok((rest(remaining), hole)),
+        Err(err) => Err(err.map(|error| nom::error::Error::new(rest(error.input), error.code))),
+    }
+}
+
+/// Skip the value of current key, returns the input at the start of next line like `key:` or `---`
+fn skip_to_next_key(input: &str) -> &str {
+    let mut input = input;
+    while let Some(end) = input.find('\n') {
+        input = &input[end + 1..];
+        let is_key = input.starts_with(|c: char| c.is_alphabetic())
+            && input.split('\n').next().is_some_and(|line| line.contains(':'));
+        if is_key || input.starts_with("---") {
+            return input;
+        }
+    }
+
+    ""
+}
+
+/// Skip the value of current variable, returns the input at the start of next line like `"var2":`,
+/// or the next front matter key.
+fn skip_to_next_variable(input: &str) -> &str {
+    let mut input = input;
+    while let Some(end) = input.find('\n') {
+        input = &input[end + 1..];
+        let line = input.split('\n').next().unwrap_or_default();
+        let is_variable = line.trim_start().starts_with(['"', '\'']) && (line.contains("\":") || line.contains("':"));
+        let is_key = !line.starts_with(char::is_whitespace) && !line.trim().is_empty();
+        if is_variable || is_key {
+            return input;
+        }
+    }
+
+    ""
+}
+
+/// Parser for the variables, the malformed variable is reported and skipped in recovering mode,
+/// so that the rest variables are still available. The duplicate key is reported as warning
+/// and the later definition overrides the earlier one.
+fn parse_variables<'a>(
+    input: &'a str,
+    diagnostics: &mut Diagnostics<'a>,
+) -> IResult<&'a str, IndexMap<String, VariableTransform>> {
+    let mut variables = IndexMap::new();
+    let mut key_spans: HashMap<String, Span> = HashMap::new();
+    let mut input = input;
+    loop {
+        let rest = skip_trivia(input);
+        // the indented line is still in the variables block, the next key starts at column 0
+        let indented = !input[..input.len() - rest.len()].rsplit('\n').next().unwrap_or_default().is_empty();
+        if !rest.is_empty() && indented && !rest.starts_with(['"', '\'']) {
+            let length = rest.find('\n').unwrap_or(rest.len());
+            diagnostics.report(
+                Severity::Error,
+                DiagnosticCode::SyntaxError,
+                "expected a variable like `\"var1\": \"demo\"`".to_string(),
+                diagnostics.span_of(rest[..length].trim_end()),
+            );
+
+            if !diagnostics.is_recovering() {
+                return Err(nom::Err::Error(nom::error::Error::new(rest, nom::error::ErrorKind::Tag)));
             }
-            HobbitHoleKey::Interaction => {
-                let (new, interaction) = parse_string(value_input)?;
-                hole.interaction = Some(InteractionType::from(&interaction));
-                cond_input = new;
+            input = skip_to_next_variable(rest);
+            continue;
+        }
+
+        if !rest.starts_with(['"', '\'']) {
+            return Ok((rest, variables));
+        }
+
+        match parse_variable(diagnostics.source(), rest) {
+            Ok((new, (key, value))) => {
+                if matches!(value, VariableTransform::PatternAction { .. } | VariableTransform::Case { .. }) {
+                    check_functions(&rest[..rest.len() - new.len()], diagnostics);
+                }
+
+                let key_end = parse_quoted_string(rest).map(|(after, _)| rest.len() - after.len()).unwrap_or(0);
+                let key_span = diagnostics.span_of(&rest[..key_end]);
+                match key_spans.get(&key) {
+                    Some(first) => diagnostics.report_related(
+                        Severity::Warning,
+                        DiagnosticCode::DuplicateKey,
+                        format!("duplicate variable `{}`", key),
+                        key_span,
+                        ("first defined here".to_string(), *first),
+                    ),
+                    None => {
+                        key_spans.insert(key.clone(), key_span);
+                    }
+                }
+                variables.insert(key, value);
+                input = new;
             }
-            HobbitHoleKey::ActionLocation => {
-                let (new, action_location) = parse_string(value_input)?;
-                hole.action_location = Some(ShireActionLocation::from(&action_location));
-                cond_input = new;
+            Err(err) if report_literal_error(&err, diagnostics) => {
+                if !diagnostics.is_recovering() {
+                    return Err(err);
+                }
+                input = skip_to_next_variable(rest);
             }
-            HobbitHoleKey::Variables => {
-                let (new, vars) = fold_many0(
-                    terminated(parse_variable, multispace0),
-                    HashMap::new,
-                    |mut acc, (k, v)| {
-                        acc.insert(k, v);
-                        acc
-                    },
-                )(value_input)?;
+            Err(err) => {
+                let name = parse_quoted_string(rest).map(|(_, name)| name).unwrap_or_default();
+                let remaining = match &err {
+                    nom::Err::Error(error) | nom::Err::Failure(error) => error.input,
+                    nom::Err::Incomplete(_) => rest,
+                };
+                let length = remaining.find('\n').unwrap_or(remaining.len()).max(1).min(remaining.len());
+                diagnostics.report(
+                    Severity::Error,
+                    DiagnosticCode::InvalidValue,
+                    format!("invalid value of variable `{}`", name),
+                    diagnostics.span_of(&remaining[..length]),
+                );
 
-                hole.variables = vars;
-                cond_input = new;
+                if !diagnostics.is_recovering() {
+                    return Err(err);
+                }
+                input = skip_to_next_variable(rest);
             }
         }
+    }
+}
 
-        let (new_input, _) = multispace0(cond_input)?;
-        cond_input = new_input;
+/// Derive the [HobbitHole] from the front matter of syntax tree, `input` is the text of tree.
+/// Each entry is parsed from its own text, so a malformed entry never swallows the next one.
+fn hobbit_hole_from<'a>(
+    front_matter: Option<&SyntaxNode>,
+    input: &'a str,
+    diagnostics: &mut Diagnostics<'a>,
+) -> IResult<&'a str, HobbitHole> {
+    let mut hole = HobbitHole::default();
+    let Some(front_matter) = front_matter else {
+        let start = diagnostics.span_of(input.trim_start()).start;
+        diagnostics.report(
+            Severity::Error,
+            DiagnosticCode::SyntaxError,
+            "expected `---` to start the front matter".to_string(),
+            Span::new(start, start + input.trim_start().chars().next().map_or(0, char::len_utf8)),
+        );
+        return match diagnostics.is_recovering() {
+            true => Ok((input, hole)),
+            false => Err(nom::Err::Error(nom::error::Error::new(input.trim_start(), nom::error::ErrorKind::Tag))),
+        };
+    };
 
-        // 检查 input 是否以 `---` 开头
-        if cond_input.starts_with("---") || cond_input.is_empty() {
-            break;
+    for entry in front_matter.nodes(SyntaxKind::Entry) {
+        let mut entry_input = &input[entry.span.start..entry.span.end];
+        while !entry_input.is_empty() {
+            entry_input = match parse_front_matter_entry(entry_input, &mut hole, diagnostics) {
+                Ok((next, _)) => skip_trivia(next),
+                Err(_) if diagnostics.is_recovering() => skip_to_next_key(entry_input),
+                Err(err) => return Err(err),
+            };
+        }
+    }
+
+    let rest = &input[front_matter.span.end..];
+    let delimiters = front_matter.children.iter().filter(|it| it.kind() == SyntaxKind::Delimiter).count();
+    if delimiters == 2 {
+        return Ok((rest, hole));
+    }
+
+    diagnostics.report(
+        Severity::Error,
+        DiagnosticCode::SyntaxError,
+        "expected `---` to close the front matter".to_string(),
+        diagnostics.span_of(rest),
+    );
+    match diagnostics.is_recovering() {
+        true => Ok((rest, hole)),
+        false => Err(nom::Err::Error(nom::error::Error::new(rest, nom::error::ErrorKind::Tag))),
+    }
+}
+
+/// Parser for one `key: value` entry of front matter, the value is set to `hole`
+fn parse_front_matter_entry<'a>(
+    cond_input: &'a str,
+    hole: &mut HobbitHole,
+    diagnostics: &mut Diagnostics<'a>,
+) -> IResult<&'a str, ()> {
+    let (input, key) = parse_object_key(cond_input).unwrap_or((cond_input, String::new()));
+    let key_len = cond_input.len() - input.len();
+    let (value_input, _) = delimited(space0, tag(":"), space0)(input).inspect_err(|_| {
+        let message = match key.is_empty() {
+            true => "expected a front matter key like `name:`".to_string(),
+            false => format!("expected `:` after key `{}`", key),
+        };
+        let end = match key.is_empty() {
+            true => cond_input.chars().next().map_or(0, char::len_utf8),
+            false => key_len,
+        };
+        let span = diagnostics.span_of(&cond_input[..end]);
+        diagnostics.report(Severity::Error, DiagnosticCode::SyntaxError, message, span);
+    })?;
+
+    let hobbit_key = HobbitHoleKey::from(key.as_str());
+    let reported = diagnostics.len();
+    let result: IResult<&str, ()> = match &hobbit_key {
+        HobbitHoleKey::Name => parse_quoted_string(value_input).map(|(new, name)| {
+            hole.name = name;
+            (new, ())
+        }),
+        HobbitHoleKey::Description => parse_string(value_input).map(|(new, description)| {
+            hole.description = Some(description.trim_end().to_string());
+            (new, ())
+        }),
+        HobbitHoleKey::Interaction => parse_string(value_input).map(|(new, interaction)| {
+            let interaction = interaction.trim();
+            hole.interaction = Some(InteractionType::from(interaction).unwrap_or_else(|| {
+                let span = diagnostics.span_of(value_input.trim_start()).start;
+                diagnostics.report(
+                    Severity::Error,
+                    DiagnosticCode::UnknownInteraction,
+                    format!("unknown interaction `{}`, fallback to `RunPanel`", interaction),
+                    Span::new(span, span + interaction.len()),
+                );
+                InteractionType::RunPanel
+            }));
+            (new, ())
+        }),
+        HobbitHoleKey::ActionLocation => parse_string(value_input).map(|(new, action_location)| {
+            let action_location = action_location.trim();
+            hole.action_location = Some(ShireActionLocation::from(action_location).unwrap_or_else(|| {
+                let span = diagnostics.span_of(value_input.trim_start()).start;
+                diagnostics.report(
+                    Severity::Error,
+                    DiagnosticCode::UnknownActionLocation,
+                    format!("unknown action location `{}`, fallback to `RunPanel`", action_location),
+                    Span::new(span, span + action_location.len()),
+                );
+                ShireActionLocation::RunPanel
+            }));
+            (new, ())
+        }),
+        HobbitHoleKey::Variables => parse_variables(value_input, diagnostics).map(|(new, vars)| {
+            hole.variables = vars;
+            (new, ())
+        }),
+        HobbitHoleKey::When => expression(diagnostics.source())(value_input).map(|(new, condition)| {
+            hole.when = Some(condition);
+            (new, ())
+        }),
+        HobbitHoleKey::BeforeStreaming => parse_lifecycle_hook(diagnostics.source(), value_input).map(|(new, hook)| {
+            hole.before_streaming = Some(hook);
+            (new, ())
+        }),
+        HobbitHoleKey::OnStreaming => parse_lifecycle_hook(diagnostics.source(), value_input).map(|(new, hook)| {
+            hole.on_streaming = Some(hook);
+            (new, ())
+        }),
+        HobbitHoleKey::AfterStreaming => parse_lifecycle_hook(diagnostics.source(), value_input).map(|(new, hook)| {
+            hole.after_streaming = Some(hook);
+            (new, ())
+        }),
+        HobbitHoleKey::OnStreamingEnd => parse_lifecycle_hook(diagnostics.source(), value_input).map(|(new, hook)| {
+            hole.on_streaming_end = Some(hook);
+            (new, ())
+        }),
+        HobbitHoleKey::Model => parse_front_matter_value(0)(value_input).map(|(new, model)| {
+            hole.model = Some(model);
+            (new, ())
+        }),
+        HobbitHoleKey::Unknown(key) => {
+            diagnostics.report(
+                Severity::Warning,
+                DiagnosticCode::UnknownKey,
+                format!("unknown key `{}`", key),
+                diagnostics.span_of(&cond_input[..key_len]),
+            );
+            parse_front_matter_value(0)(value_input).map(|(new, value)| {
+                hole.user_data.insert(key.clone(), value);
+                (new, ())
+            })
+        }
+    };
+
+    if let Ok((new, _)) = &result {
+        if matches!(
+            hobbit_key,
+            HobbitHoleKey::BeforeStreaming
+                | HobbitHoleKey::OnStreaming
+                | HobbitHoleKey::AfterStreaming
+                | HobbitHoleKey::OnStreamingEnd
+        ) {
+            check_functions(&value_input[..value_input.len() - new.len()], diagnostics);
         }
     }
 
-    let (input, _) = parse_frontmatter_end(cond_input)?;
-    Ok((input, hole))
+    result.inspect_err(|err| {
+        // the value parser may have reported a more precise diagnostic already
+        if diagnostics.len() > reported || report_literal_error(err, diagnostics) {
+            return;
+        }
+
+        if let nom::Err::Error(error) | nom::Err::Failure(error) = err {
+            let remaining = error.input;
+            let length = remaining.find('\n').unwrap_or(remaining.len()).max(1).min(remaining.len());
+            diagnostics.report(
+                Severity::Error,
+                DiagnosticCode::InvalidValue,
+                format!("invalid value of `{}`, expected {}", key, hobbit_key.expected()),
+                diagnostics.span_of(&remaining[..length]),
+            );
+        }
+    })
 }
 
 // Parser for the entire file
 fn parse_file(input: &str) -> IResult<&str, ShireFile> {
-    let (input, variables) = parse_hobbit_hole(input)?;
-    let (input, body) = many0(parse_string)(input)?; // Simplified for demonstration
-    /// collect Variable Table in body
-    Ok((input, ShireFile { hobbit: variables, body }))
+    let tree = parse_cst(input);
+    let code = mask_comments(input, tree.child(SyntaxKind::FrontMatter));
+    // the rest of masked code is mapped back to `input` at the same position
+    let rest = |remaining: &str| &input[code.len() - remaining.len()..];
+    match parse_file_with(&tree, &code, &mut Diagnostics::new(&code)) {
+        Ok((remaining, file)) => Ok((rest(remaining), file)),
+        Err(err) => Err(err.map(|error| nom::error::Error::new(rest(error.input), error.code))),
+    }
+}
+
+/// Derive the [ShireFile] from the syntax `tree` of source, `input` is the source with masked comments
+fn parse_file_with<'a>(
+    tree: &SyntaxNode,
+    input: &'a str,
+    diagnostics: &mut Diagnostics<'a>,
+) -> IResult<&'a str, ShireFile> {
+    let front_matter = tree.child(SyntaxKind::FrontMatter);
+    let (rest, variables) = hobbit_hole_from(front_matter, input, diagnostics)?;
+    let comments = front_matter.map(collect_comments).unwrap_or_default();
+    let body = parse_body(rest, diagnostics.span_of(rest).start);
+    Ok(("", ShireFile { hobbit: variables, body, comments }))
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseResult {
+    /// The parsed file, which is `None` when the file does not match the grammar
+    pub file: Option<ShireFile>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ParseResult {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|it| it.severity == Severity::Error)
+    }
+}
+
+/// Parse the Shire file, the diagnostics like unknown keys or invalid values are returned alongside the AST,
+/// use [Diagnostic::render] to show them to the script author.
+pub fn parse(source: &str) -> ParseResult {
+    let tree = parse_cst(source);
+    let code = mask_comments(source, tree.child(SyntaxKind::FrontMatter));
+    let mut diagnostics = Diagnostics::new(&code);
+    let file = match parse_file_with(&tree, &code, &mut diagnostics) {
+        Ok((_, file)) => Some(file),
+        Err(err) => {
+            if !diagnostics.has_errors() {
+                let remaining = match &err {
+                    nom::Err::Error(error) | nom::Err::Failure(error) => error.input,
+                    nom::Err::Incomplete(_) => "",
+                };
+                let token = remaining.split_whitespace().next().unwrap_or_default();
+                let message = match token.is_empty() {
+                    true => "unexpected end of file".to_string(),
+                    false => format!("unexpected `{}`", token),
+                };
+                let start = diagnostics.span_of(remaining.trim_start()).start;
+                diagnostics.report(
+                    Severity::Error,
+                    DiagnosticCode::SyntaxError,
+                    message,
+                    Span::new(start, start + token.len()),
+                );
+            }
+            None
+        }
+    };
+
+    ParseResult { file, diagnostics: diagnostics.into_vec() }
+}
+
+/// Parse the Shire file in recovering mode for editor tooling, the malformed key or variable is reported and
+/// skipped to the next line, so the file is always returned with everything that did parse.
+pub fn parse_recovering(source: &str) -> ParseResult {
+    let tree = parse_cst(source);
+    let code = mask_comments(source, tree.child(SyntaxKind::FrontMatter));
+    let mut diagnostics = Diagnostics::recovering(&code);
+    let file = match parse_file_with(&tree, &code, &mut diagnostics) {
+        Ok((_, file)) => file,
+        Err(_) => ShireFile { hobbit: HobbitHole::default(), body: vec![], comments: vec![] },
+    };
+
+    ParseResult { file: Some(file), diagnostics: diagnostics.into_vec() }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ast::shire_expression::{
+        Comparison, LogicalExpression, MethodCall, NotExpression, Operator, OperatorType, Statement, Value,
+    };
+    use crate::body::BodyNodeKind;
+    use crate::runtime::{Environment, Value as RuntimeValue};
+
+    fn string(value: &str) -> FrontMatterType {
+        FrontMatterType::STRING(value.to_string())
+    }
+
+    fn string_args(args: &[&str]) -> Vec<FunctionArg> {
+        args.iter().map(|it| FunctionArg::positional(string(it))).collect()
+    }
 
     #[test]
     fn test_should_parse_multiple_frontmatter_config() {
@@ -453,24 +1369,72 @@ variables:
                     action_location: Some(ShireActionLocation::ContextMenu),
                     variables: vec![("var1".to_string(), VariableTransform::String("demo".to_string()))]
                         .into_iter()
-                        .collect()
+                        .collect(),
+                    ..Default::default()
                 }
             ))
         );
     }
 
     #[test]
-    fn test_parse_regex_block() {
-        assert_eq!(
-            parse_pattern_actions("/.*.java/ { grep(\"error.log\") | sort | xargs(\"rm\") }"),
+    fn test_parse_regex_literal() {
+        let (rest, pattern) = parse_regex_literal(r"/src\/main\/.*\.java/ {").unwrap();
+        assert_eq!(rest, " {");
+        assert_eq!(pattern, RegexPattern::new(r"src/main/.*\.java", "").unwrap());
+        assert!(pattern.is_match("src/main/Foo.java"));
+        assert_eq!(pattern.to_string(), r"/src\/main\/.*\.java/");
+
+        let (_, pattern) = parse_regex_literal(r"/a\\/i").unwrap();
+        assert_eq!((pattern.pattern.as_str(), pattern.flags.as_str()), (r"a\\", "i"));
+
+        let (_, pattern) = parse_regex_literal("/error/i").unwrap();
+        assert!(pattern.is_match("ERROR: disk is full"));
+
+        assert!(matches!(parse_regex_literal("//"), Err(nom::Err::Error(_))));
+        assert!(matches!(parse_regex_literal("/(/"), Err(nom::Err::Failure(_))));
+        assert!(matches!(parse_regex_literal("/error/g"), Err(nom::Err::Failure(_))));
+    }
+
+    #[test]
+    fn test_report_invalid_regex() {
+        let source = r#"---
+variables:
+  "var1": /src\/(main/ { grep("a") }
+  "var2": /.*.log/g { grep("b") }
+  "var3": /a{2}\/b/i { head("x") }
+---
+"#;
+
+        let result = parse_recovering(source);
+        let diagnostics: Vec<(DiagnosticCode, usize, usize, &str)> = result
+            .diagnostics
+            .iter()
+            .map(|it| (it.code, it.line, it.column, it.message.as_str()))
+            .collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                (DiagnosticCode::InvalidRegex, 3, 11, "invalid regex `src/(main`"),
+                (DiagnosticCode::InvalidRegex, 4, 11, "unknown regex flag `g`"),
+                (DiagnosticCode::InvalidArgument, 5, 24, "`head` expects a number of lines, found `x`"),
+            ]
+        );
+        assert_eq!(result.diagnostics[0].span.len(), r"/src\/(main/".len());
+    }
+
+    #[test]
+    fn test_parse_regex_block() {
+        let input = "/.*.java/ { grep(\"error.log\") | sort | xargs(\"rm\") }";
+        assert_eq!(
+            parse_pattern_actions(input, input),
             Ok((
                 "",
                 VariableTransform::PatternAction {
-                    pattern: ".*.java".to_string(),
+                    pattern: RegexPattern::new(".*.java", "").unwrap(),
                     command: Function::Functions(vec![
-                        ("grep".to_string(), vec!["error.log".to_string()]),
+                        ("grep".to_string(), string_args(&["error.log"])),
                         ("sort".to_string(), vec![]),
-                        ("xargs".to_string(), vec!["rm".to_string()])
+                        ("xargs".to_string(), string_args(&["rm"]))
                     ])
                 }
             ))
@@ -502,17 +1466,41 @@ variables:
                         ("var1".to_string(), VariableTransform::String("demo".to_string())),
                         ("var1".to_string(), VariableTransform::Integer(42)),
                         ("var2".to_string(), VariableTransform::PatternAction {
-                            pattern: ".*.java".to_string(),
+                            pattern: RegexPattern::new(".*.java", "").unwrap(),
                             command: Function::Functions(vec![
-                                ("grep".to_string(), vec!["error.log".to_string()]),
+                                ("grep".to_string(), string_args(&["error.log"])),
                                 ("sort".to_string(), vec![]),
-                                ("xargs".to_string(), vec!["rm".to_string()])
+                                ("xargs".to_string(), string_args(&["rm"]))
                             ])
                         })
-                    ].into_iter().collect()
+                    ].into_iter().collect(),
+                    ..Default::default()
                 }
             ))
         );
+
+        let result = parse(input);
+        let hole = result.file.unwrap().hobbit;
+        assert_eq!(hole.variables.keys().collect::<Vec<_>>(), vec!["var1", "var2"]);
+        assert_eq!(hole.variables.get("var1"), Some(&VariableTransform::Integer(42)));
+
+        assert_eq!(result.diagnostics.len(), 1);
+        let diagnostic = &result.diagnostics[0];
+        assert_eq!(
+            (diagnostic.severity, diagnostic.code, diagnostic.line, diagnostic.column),
+            (Severity::Warning, DiagnosticCode::DuplicateKey, 5, 3)
+        );
+        assert_eq!(diagnostic.message, "duplicate variable `var1`");
+        assert_eq!(diagnostic.span.len(), "\"var1\"".len());
+        assert_eq!((diagnostic.related[0].line, diagnostic.related[0].column), (4, 3));
+        assert_eq!(diagnostic.related[0].message, "first defined here");
+    }
+
+    #[test]
+    fn test_keep_declaration_order() {
+        let input = "---\nvariables:\n  \"zeta\": \"z\"\n  \"alpha\": 1\n  \"mid\": true\n---\n";
+        let (_, hole) = parse_hobbit_hole(input).unwrap();
+        assert_eq!(hole.variables.keys().collect::<Vec<_>>(), vec!["zeta", "alpha", "mid"]);
     }
 
     #[test]
@@ -525,11 +1513,12 @@ variables:
 
 $var1
 "#;
+        let body = input.find("$var1").unwrap();
 
         assert_eq!(
             parse_file(input),
             Ok((
-                "\n",
+                "",
                 ShireFile {
                     hobbit: HobbitHole {
                         name: "".to_string(),
@@ -538,16 +1527,21 @@ $var1
                         action_location: None,
                         variables: vec![
                             ("var2".to_string(), VariableTransform::PatternAction {
-                                pattern: ".*.java".to_string(),
+                                pattern: RegexPattern::new(".*.java", "").unwrap(),
                                 command: Function::Functions(vec![
-                                    ("grep".to_string(), vec!["error.log".to_string()]),
+                                    ("grep".to_string(), string_args(&["error.log"])),
                                     ("sort".to_string(), vec![]),
-                                    ("xargs".to_string(), vec!["rm".to_string()])
+                                    ("xargs".to_string(), string_args(&["rm"]))
                                 ])
                             })
-                        ].into_iter().collect()
+                        ].into_iter().collect(),
+                        ..Default::default()
                     },
-                    body: vec!["$var1".to_string()]
+                    body: vec![
+                        BodyNode { kind: BodyNodeKind::Variable("var1".to_string()), span: Span::new(body, body + 5) },
+                        BodyNode { kind: BodyNodeKind::Text("\n".to_string()), span: Span::new(body + 5, body + 6) },
+                    ],
+                    comments: vec![],
                 }
             ))
         );
@@ -593,46 +1587,918 @@ variables:
         assert_eq!(
             case_block,
             &VariableTransform::Case {
-                pattern: ".*.log".to_string(),
-                cases: vec![
-                    ("error".to_string(), Action {
-                        command: Function::Functions(vec![
-                            ("grep".to_string(), vec!["ERROR".to_string()]),
-                            ("sort".to_string(), vec![]),
-                            ("xargs".to_string(), vec!["notify_admin".to_string()])
-                        ])
-                    }),
-                    ("warn".to_string(), Action {
-                        command: Function::Functions(vec![
-                            ("grep".to_string(), vec!["WARN".to_string()]),
-                            ("sort".to_string(), vec![]),
-                            ("xargs".to_string(), vec!["notify_admin".to_string()])
-                        ])
-                    }),
-                    ("info".to_string(), Action {
-                        command: Function::Functions(vec![
-                            ("grep".to_string(), vec!["INFO".to_string()]),
-                            ("sort".to_string(), vec![]),
-                            ("xargs".to_string(), vec!["notify_user".to_string()])
-                        ])
-                    }),
-                    // default
-                    ("default".to_string(), Action {
-                        command: Function::Functions(vec![
-                            ("grep".to_string(), vec!["ERROR".to_string()]),
-                            ("sort".to_string(), vec![]),
-                            ("xargs".to_string(), vec!["notify_admin".to_string()])
-                        ])
-                    })
-                ].into_iter().collect(),
-                default: Some(Box::new(Action {
-                    command: Function::Functions(vec![
-                        ("grep".to_string(), vec!["ERROR".to_string()]),
-                        ("sort".to_string(), vec![]),
-                        ("xargs".to_string(), vec!["notify_admin".to_string()])
-                    ])
-                }))
+                pattern: RegexPattern::new(".*.log", "").unwrap(),
+                case: ConditionCase::new(
+                    FrontMatterType::STRING("$0".to_string()),
+                    vec![
+                        pipeline_arm(FrontMatterType::STRING("error".to_string()), "ERROR", "notify_admin"),
+                        pipeline_arm(FrontMatterType::STRING("warn".to_string()), "WARN", "notify_admin"),
+                        pipeline_arm(FrontMatterType::STRING("info".to_string()), "INFO", "notify_user"),
+                        pipeline_arm(FrontMatterType::IDENTIFIER("default".to_string()), "ERROR", "notify_admin"),
+                    ],
+                ),
+            }
+        );
+    }
+
+    fn pipeline_arm(key: FrontMatterType, level: &str, target: &str) -> CaseKeyValue {
+        let processor = Processor::new(vec![
+            PatternActionFunc::Grep { patterns: vec![string(level)] },
+            PatternActionFunc::Sort { arguments: vec![] },
+            PatternActionFunc::Xargs { variables: vec![string(target)] },
+        ]);
+        CaseKeyValue::new(key, FrontMatterType::EXPRESSION(StatementType::Processor(processor)))
+    }
+
+    #[test]
+    fn test_parse_flexible_case_block() {
+        let input = r#"{
+  case $fileName {
+    "*.java" { grep("class") }
+    /.*\.kts?/i { grep("fun") }
+    "src/*" {
+      case "$language" {
+        "rust" { grep("fn") }
+      }
+    }
+  }
+}"#;
+
+        let (rest, case) = parse_case_match(input, input).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(case.subject(), &FrontMatterType::VARIABLE("fileName".to_string()));
+        assert_eq!(case.cases().len(), 3);
+        assert_eq!(case.cases()[1].key(), &FrontMatterType::REGEX(RegexPattern::new(r".*\.kts?", "i").unwrap()));
+
+        let key = |subject: &str| case.find(subject).map(|it| it.key().display());
+        assert_eq!(key("Main.java"), Some("\"*.java\"".to_string()));
+        assert_eq!(key("build.gradle.KTS"), Some(r"/.*\.kts?/i".to_string()));
+        assert_eq!(key("src/lib.rs"), Some("\"src/*\"".to_string()));
+        assert_eq!(key("README.md"), None);
+
+        match case.cases()[2].value() {
+            FrontMatterType::EXPRESSION(StatementType::ConditionCase(nested)) => {
+                assert_eq!(nested.subject(), &FrontMatterType::STRING("$language".to_string()));
+            }
+            other => panic!("unexpected value {:?}", other),
+        }
+
+        let display = case.display();
+        assert_eq!(
+            display,
+            r#"case $fileName {
+  "*.java" { grep("class") }
+  /.*\.kts?/i { grep("fun") }
+  "src/*" {
+    case "$language" {
+      "rust" { grep("fn") }
+    }
+  }
+}"#
+        );
+        assert_eq!(parse_condition_case(&display, &display), Ok(("", case)));
+    }
+
+    #[test]
+    fn test_evaluate_case_arm_scope() {
+        let input = r#"{
+  case $fileName {
+    /(\w+)\.kt/ {
+      case "$1" {
+        "Main" { grep("main") }
+        default { sort }
+      }
+    }
+  }
+}"#;
+
+        let (_, case) = parse_case_match(input, input).unwrap();
+        let evaluate = |file_name: &str| {
+            let env = Environment::from(HashMap::from([("fileName".to_string(), file_name.to_string())]));
+            case.evaluate(&env)
+        };
+
+        let grep = PatternActionFunc::Grep { patterns: vec![string("main")] };
+        assert_eq!(evaluate("Main.kt"), Ok(RuntimeValue::Pipeline(vec![grep])));
+        assert_eq!(evaluate("Util.kt"), Ok(RuntimeValue::Pipeline(vec![PatternActionFunc::Sort { arguments: vec![] }])));
+        assert_eq!(evaluate("Main.java"), Err("no case matches `Main.java`".to_string()));
+    }
+
+    #[test]
+    fn test_parse_typed_function_args() {
+        let input = concat!(
+            r#"{ sed( $pattern , "x" ) | head(10) | searching("login", threshold=0.75) "#,
+            r#"| print(trim($name), $a.length() > 1, false) | foo(n=5) | head($n) }"#,
+        );
+
+        let (rest, functions) = parse_actions(input, input).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(
+            functions[0],
+            ("sed".to_string(), vec![
+                FunctionArg::positional(FrontMatterType::VARIABLE("pattern".to_string())),
+                FunctionArg::positional(FrontMatterType::STRING("x".to_string())),
+            ])
+        );
+        assert_eq!(functions[1], ("head".to_string(), vec![FunctionArg::positional(FrontMatterType::NUMBER(10))]));
+        assert_eq!(functions[2].1[1], FunctionArg::named("threshold".to_string(), FrontMatterType::FLOAT(0.75)));
+
+        let print = functions[3].1.iter().map(|it| it.value.clone()).collect::<Vec<_>>();
+        assert_eq!(print.iter().map(|it| it.display()).collect::<Vec<_>>(), vec![
+            "trim($name)",
+            "$a.length() > 1",
+            "false",
+        ]);
+
+        let foo = functions[4].1.clone();
+        let head = functions[5].1.clone();
+        assert_eq!(to_pattern_action_funcs(functions), vec![
+            PatternActionFunc::Sed {
+                pattern: FrontMatterType::VARIABLE("pattern".to_string()),
+                replacements: string("x"),
+                is_regex: true,
+            },
+            PatternActionFunc::Head { number: 10 },
+            PatternActionFunc::Searching { text: string("login"), threshold: 0.75 },
+            PatternActionFunc::Print { texts: print },
+            PatternActionFunc::ToolchainFunction { func_name: "foo".to_string(), args: foo },
+            PatternActionFunc::ToolchainFunction { func_name: "head".to_string(), args: head },
+        ]);
+    }
+
+    #[test]
+    fn test_resolve_function_args_when_running() {
+        let input = r#"{ head($n) | sed($p, "x") | print(trim($name)) }"#;
+        let (_, functions) = parse_actions(input, input).unwrap();
+        let functions = to_pattern_action_funcs(functions);
+
+        let mut env = Environment::new();
+        env.define("n", 5i64);
+        env.define("p", "a.b");
+        env.define("name", "Shire");
+        let resolved = functions.iter().map(|it| it.resolve(&env)).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(resolved[0], PatternActionFunc::Head { number: 5 });
+        assert_eq!(
+            resolved[1],
+            PatternActionFunc::Sed { pattern: string("a.b"), replacements: string("x"), is_regex: true }
+        );
+        assert_eq!(resolved[2].to_string(), r#"print(trim("Shire"))"#);
+
+        env.define("n", "ten");
+        env.define("p", "(");
+        assert_eq!(functions[0].resolve(&env), Err("`head` expects a number of lines, found `ten`".to_string()));
+        assert_eq!(functions[1].resolve(&env), Err("invalid regex `(` in `sed`".to_string()));
+    }
+
+    #[test]
+    fn test_parse_when_condition() {
+        let input = r#"
+---
+name: "Test"
+when: $fileName.contains(".java") && $filePath.endsWith("Test.java")
+---
+"#;
+
+        let (_, hole) = parse_hobbit_hole(input).unwrap();
+        assert_eq!(hole.name, "Test".to_string());
+        assert_eq!(
+            hole.when,
+            Some(StatementType::LogicalExpression(LogicalExpression::new(
+                StatementType::MethodCall(MethodCall::new(
+                    FrontMatterType::VARIABLE("fileName".to_string()),
+                    FrontMatterType::IDENTIFIER("contains".to_string()),
+                    Some(vec![FrontMatterType::STRING(".java".to_string())]),
+                )),
+                OperatorType::And,
+                StatementType::MethodCall(MethodCall::new(
+                    FrontMatterType::VARIABLE("filePath".to_string()),
+                    FrontMatterType::IDENTIFIER("endsWith".to_string()),
+                    Some(vec![FrontMatterType::STRING("Test.java".to_string())]),
+                )),
+            )))
+        );
+
+        let start = input.find("$fileName").unwrap();
+        let end = input.rfind("\n---").unwrap();
+        assert_eq!(hole.when.unwrap().span(), Span::new(start, end));
+    }
+
+    #[test]
+    fn test_parse_condition_precedence() {
+        let source = r#"!$a.isEmpty || $b == "x" && $c != "y""#;
+        let (input, condition) = expression(source)(source).unwrap();
+        assert_eq!(input, "");
+        assert_eq!(
+            condition,
+            StatementType::LogicalExpression(LogicalExpression::new(
+                StatementType::NotExpression(NotExpression::new(StatementType::MethodCall(MethodCall::new(
+                    FrontMatterType::VARIABLE("a".to_string()),
+                    FrontMatterType::IDENTIFIER("isEmpty".to_string()),
+                    None,
+                )))),
+                OperatorType::Or,
+                StatementType::LogicalExpression(LogicalExpression::new(
+                    StatementType::Comparison(Comparison::new(
+                        FrontMatterType::VARIABLE("b".to_string()),
+                        Operator::new(OperatorType::Equal),
+                        FrontMatterType::STRING("x".to_string()),
+                    )),
+                    OperatorType::And,
+                    StatementType::Comparison(Comparison::new(
+                        FrontMatterType::VARIABLE("c".to_string()),
+                        Operator::new(OperatorType::NotEqual),
+                        FrontMatterType::STRING("y".to_string()),
+                    )),
+                )),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_lifecycle_hooks() {
+        let input = r#"
+---
+name: "Hooks"
+onStreaming: { logging }
+afterStreaming: { parseCode | saveFile("out.java") }
+onStreamingEnd: {
+  case "$output" {
+    "error" { notify("failed") }
+    default { print("done") }
+  }
+}
+---
+"#;
+
+        let (input, hole) = parse_hobbit_hole(input).unwrap();
+        assert_eq!(input, "");
+        let lowered = |hook: Option<LifecycleHook>| hook.unwrap().to_pattern_action_funcs();
+        assert_eq!(hole.before_streaming, None);
+        assert_eq!(
+            lowered(hole.on_streaming),
+            vec![PatternActionFunc::ToolchainFunction { func_name: "logging".to_string(), args: vec![] }]
+        );
+        assert_eq!(
+            hole.after_streaming,
+            Some(LifecycleHook::Action {
+                command: Function::Functions(vec![
+                    ("parseCode".to_string(), vec![]),
+                    ("saveFile".to_string(), string_args(&["out.java"])),
+                ]),
+            })
+        );
+        assert_eq!(
+            lowered(hole.after_streaming),
+            vec![
+                PatternActionFunc::ToolchainFunction { func_name: "parseCode".to_string(), args: vec![] },
+                PatternActionFunc::ToolchainFunction {
+                    func_name: "saveFile".to_string(),
+                    args: string_args(&["out.java"]),
+                },
+            ]
+        );
+
+        let arm = |key: FrontMatterType, func: PatternActionFunc| {
+            CaseKeyValue::new(key, FrontMatterType::EXPRESSION(StatementType::Processor(Processor::new(vec![func]))))
+        };
+        assert_eq!(
+            lowered(hole.on_streaming_end),
+            vec![PatternActionFunc::CaseMatch {
+                case: ConditionCase::new(
+                    FrontMatterType::STRING("$output".to_string()),
+                    vec![
+                        arm(
+                            FrontMatterType::STRING("error".to_string()),
+                            PatternActionFunc::Notify { message: string("failed") },
+                        ),
+                        arm(
+                            FrontMatterType::IDENTIFIER("default".to_string()),
+                            PatternActionFunc::Print { texts: vec![string("done")] },
+                        ),
+                    ],
+                ),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_nested_object() {
+        let input = r#"
+---
+name: "Model"
+model:
+  provider: "openai"
+  temperature: 0
+  stream: true
+
+  options:
+    stop:
+      - "END"
+      - 42
+    tools: ["grep", sed]
+---
+"#;
+
+        let (input, hole) = parse_hobbit_hole(input).unwrap();
+        assert_eq!(input, "");
+        assert_eq!(hole.name, "Model".to_string());
+
+        let options = FrontMatterType::OBJECT(IndexMap::from([
+            ("stop".to_string(), FrontMatterType::ARRAY(vec![
+                FrontMatterType::STRING("END".to_string()),
+                FrontMatterType::NUMBER(42),
+            ])),
+            ("tools".to_string(), FrontMatterType::ARRAY(vec![
+                FrontMatterType::STRING("grep".to_string()),
+                FrontMatterType::STRING("sed".to_string()),
+            ])),
+        ]));
+        assert_eq!(
+            hole.model,
+            Some(FrontMatterType::OBJECT(IndexMap::from([
+                ("provider".to_string(), FrontMatterType::STRING("openai".to_string())),
+                ("temperature".to_string(), FrontMatterType::NUMBER(0)),
+                ("stream".to_string(), FrontMatterType::BOOLEAN(true)),
+                ("options".to_string(), options),
+            ])))
+        );
+        match hole.model.unwrap() {
+            FrontMatterType::OBJECT(model) => {
+                assert_eq!(model.keys().collect::<Vec<_>>(), vec!["provider", "temperature", "stream", "options"])
             }
+            other => panic!("unexpected model {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_user_data() {
+        let source = r#"---
+name: "Config"
+config:
+  retries: 3
+  extra:
+    z: 1
+    a: 2
+labels: ["review", "java"]
+---
+"#;
+
+        let result = parse(source);
+        let codes: Vec<DiagnosticCode> = result.diagnostics.iter().map(|it| it.code).collect();
+        assert_eq!(codes, vec![DiagnosticCode::UnknownKey, DiagnosticCode::UnknownKey]);
+
+        let hole = result.file.unwrap().hobbit;
+        assert_eq!(hole.name, "Config".to_string());
+        assert_eq!(hole.user_data.keys().collect::<Vec<_>>(), vec!["config", "labels"]);
+        let extra = FrontMatterType::OBJECT(IndexMap::from([
+            ("z".to_string(), FrontMatterType::NUMBER(1)),
+            ("a".to_string(), FrontMatterType::NUMBER(2)),
+        ]));
+        assert_eq!(extra.display(), r#"{"z": 1, "a": 2}"#);
+        let key = r#"say "hi"\"#.to_string();
+        let quoted = FrontMatterType::OBJECT(IndexMap::from([(key, FrontMatterType::NUMBER(1))]));
+        assert_eq!(quoted.display(), r#"{"say \"hi\"\\": 1}"#);
+        assert_eq!(
+            hole.user_data.get("config"),
+            Some(&FrontMatterType::OBJECT(IndexMap::from([
+                ("retries".to_string(), FrontMatterType::NUMBER(3)),
+                ("extra".to_string(), extra),
+            ])))
+        );
+        assert_eq!(
+            hole.user_data.get("labels").map(|it| it.display()),
+            Some(r#"["review", "java"]"#.to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_user_data_keys() {
+        let result = parse("---\nmax_tokens: 1\ntop-p: 0.5\n\"stop words\": [\"a\"]\n---\n");
+        assert_eq!(result.diagnostics.iter().map(|it| it.message.as_str()).collect::<Vec<_>>(), vec![
+            "unknown key `max_tokens`",
+            "unknown key `top-p`",
+            "unknown key `stop words`",
+        ]);
+        assert_eq!((result.diagnostics[2].span.start, result.diagnostics[2].span.end), (29, 41));
+
+        let hole = result.file.unwrap().hobbit;
+        assert_eq!(hole.user_data.keys().collect::<Vec<_>>(), vec!["max_tokens", "top-p", "stop words"]);
+        assert_eq!(hole.user_data.get("top-p"), Some(&FrontMatterType::FLOAT(0.5)));
+    }
+
+    #[test]
+    fn test_parse_comments() {
+        let input = r#"---
+# TODO: rename
+name: "Comment"
+variables:
+  // the files to review
+  "files": /.*\.rs/ { sort }
+  "doc": """
+# not a comment
+"""
+model:
+  # provider: "azure"
+  provider: "openai"
+---
+Review $files
+"#;
+
+        let result = parse(input);
+        assert_eq!(result.diagnostics, vec![]);
+        let file = result.file.unwrap();
+        assert_eq!(file.hobbit.name, "Comment".to_string());
+        assert_eq!(file.hobbit.variables.keys().collect::<Vec<_>>(), vec!["files", "doc"]);
+        assert_eq!(
+            file.hobbit.model,
+            Some(FrontMatterType::OBJECT(IndexMap::from([
+                ("provider".to_string(), FrontMatterType::STRING("openai".to_string())),
+            ])))
+        );
+
+        let comments: Vec<&str> = file.comments.iter().map(|it| it.text.as_str()).collect();
+        assert_eq!(comments, vec!["# TODO: rename", "// the files to review", "# provider: \"azure\""]);
+        assert_eq!(file.comments[0].span, Span::new(4, 18));
+    }
+
+    #[test]
+    fn test_parse_comments_in_blocks() {
+        let input = r#"---
+name: "C# helper" # trailing
+description: See https://example.com/#intro
+when: $fileName.endsWith(".kt") // why
+variables:
+  "log": /.*.log/ {
+    case "$0" {
+      # errors first
+      "error" { grep("ERROR") } // alert
+      default { sort }
+    }
+  }
+  "files": /.*\.rs/ {
+    // the sources
+    grep("a#b") # inline
+    | sort
+  }
+afterStreaming: {
+  # save it
+  parseCode | saveFile("out.java")
+}
+---
+"#;
+
+        let result = parse(input);
+        assert_eq!(result.diagnostics, vec![]);
+        let file = result.file.unwrap();
+        assert_eq!(file.hobbit.name, "C# helper".to_string());
+        assert_eq!(file.hobbit.description, Some("See https://example.com/#intro".to_string()));
+        assert_eq!(file.hobbit.when.map(|it| it.display()), Some(r#"$fileName.endsWith(".kt")"#.to_string()));
+        assert_eq!(file.hobbit.after_streaming.unwrap().to_pattern_action_funcs().len(), 2);
+
+        let case = match file.hobbit.variables.get("log").unwrap() {
+            VariableTransform::Case { case, .. } => case.display(),
+            other => panic!("unexpected variable {:?}", other),
+        };
+        assert_eq!(case, "case \"$0\" {\n  \"error\" { grep(\"ERROR\") }\n  default { sort }\n}");
+        match file.hobbit.variables.get("files").unwrap() {
+            VariableTransform::PatternAction { command, .. } => assert_eq!(
+                command.to_pattern_action_funcs(),
+                vec![
+                    PatternActionFunc::Grep { patterns: vec![string("a#b")] },
+                    PatternActionFunc::Sort { arguments: vec![] },
+                ]
+            ),
+            other => panic!("unexpected variable {:?}", other),
+        }
+
+        let comments: Vec<&str> = file.comments.iter().map(|it| it.text.as_str()).collect();
+        assert_eq!(
+            comments,
+            vec!["# trailing", "// why", "# errors first", "// alert", "// the sources", "# inline", "# save it"]
+        );
+        assert_eq!(&input[file.comments[0].span.start..file.comments[0].span.end], "# trailing");
+
+        // the comment takes the place of value, so the description is missing
+        let result = parse("---\ndescription: # not a description\n---\n");
+        assert_eq!(result.file, None);
+        assert_eq!(result.diagnostics[0].message, "invalid value of `description`, expected a text");
+        assert_eq!((result.diagnostics[0].line, result.diagnostics[0].column), (2, 13));
+    }
+
+    #[test]
+    fn test_parse_array_of_objects() {
+        let input = r#"
+---
+model:
+  - name: "gpt-4"
+    temperature: 1
+  - "gpt-3.5"
+variables:
+  "var1": "demo"
+---
+"#;
+
+        let (input, hole) = parse_hobbit_hole(input).unwrap();
+        assert_eq!(input, "");
+        assert_eq!(
+            hole.model,
+            Some(FrontMatterType::ARRAY(vec![
+                FrontMatterType::OBJECT(IndexMap::from([
+                    ("name".to_string(), FrontMatterType::STRING("gpt-4".to_string())),
+                    ("temperature".to_string(), FrontMatterType::NUMBER(1)),
+                ])),
+                FrontMatterType::STRING("gpt-3.5".to_string()),
+            ]))
+        );
+        assert_eq!(hole.variables.get("var1"), Some(&VariableTransform::String("demo".to_string())));
+    }
+
+    #[test]
+    fn test_parse_string_literals() {
+        let parsed = |input| parse_quoted_string(input).map(|(rest, value)| (value, rest));
+
+        assert_eq!(parsed(r#""""#), Ok(("".to_string(), "")));
+        assert_eq!(parsed(r#""say \"hi\"\n\t\u{1F600}A" rest"#), Ok(("say \"hi\"\n\t😀A".to_string(), " rest")));
+        assert_eq!(parsed(r#"'it\'s "quoted"'"#), Ok(("it's \"quoted\"".to_string(), "")));
+        assert_eq!(parsed(r#""\d+\.java""#), Ok((r"\d+\.java".to_string(), "")));
+        assert_eq!(parsed("\"\"\"\nline \"one\"\n\\n two\n\"\"\""), Ok(("line \"one\"\n\\n two\n".to_string(), "")));
+
+        assert!(parsed("\"unclosed\n\"").is_err());
+        assert!(parsed(r#""\u{zz}""#).is_err());
+    }
+
+    #[test]
+    fn test_parse_strings_in_front_matter() {
+        let input = r#"---
+name: 'Review "PR"'
+variables:
+  'var1': ""
+  "var2": /.*.java/ { print("say \"hi\"\n", 'a|b') | sed("\s+", " ") }
+  "var3": """
+Summarize the code:
+  - keep it short
+"""
+---
+"#;
+
+        let (_, hole) = parse_hobbit_hole(input).unwrap();
+        assert_eq!(hole.name, "Review \"PR\"".to_string());
+        assert_eq!(hole.variables.get("var1"), Some(&VariableTransform::String("".to_string())));
+        assert_eq!(
+            hole.variables.get("var2"),
+            Some(&VariableTransform::PatternAction {
+                pattern: RegexPattern::new(".*.java", "").unwrap(),
+                command: Function::Functions(vec![
+                    ("print".to_string(), string_args(&["say \"hi\"\n", "a|b"])),
+                    ("sed".to_string(), string_args(&[r"\s+", " "])),
+                ]),
+            })
+        );
+        assert_eq!(
+            hole.variables.get("var3"),
+            Some(&VariableTransform::String("Summarize the code:\n  - keep it short\n".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_numbers_and_booleans() {
+        let input = r#"---
+variables:
+  "limit": 9007199254740993
+  "offset": -3
+  "ratio": 0.75
+  "enabled": true
+  "matched": /.*.kt/ { searching("login", 0.75) | head(5) }
+model:
+  name: gpt-3.5-turbo
+  temperature: 0.2
+  seed: -1e3
+---
+"#;
+
+        let (_, hole) = parse_hobbit_hole(input).unwrap();
+        assert_eq!(hole.variables.get("limit"), Some(&VariableTransform::Integer(9007199254740993)));
+        assert_eq!(hole.variables.get("offset"), Some(&VariableTransform::Integer(-3)));
+        assert_eq!(hole.variables.get("ratio"), Some(&VariableTransform::Float(0.75)));
+        assert_eq!(hole.variables.get("enabled"), Some(&VariableTransform::Boolean(true)));
+        match hole.variables.get("matched").unwrap() {
+            VariableTransform::PatternAction { command, .. } => assert_eq!(
+                command.to_pattern_action_funcs(),
+                vec![
+                    PatternActionFunc::Searching { text: string("login"), threshold: 0.75 },
+                    PatternActionFunc::Head { number: 5 },
+                ]
+            ),
+            other => panic!("unexpected variable {:?}", other),
+        }
+        assert_eq!(
+            hole.model,
+            Some(FrontMatterType::OBJECT(IndexMap::from([
+                ("name".to_string(), FrontMatterType::STRING("gpt-3.5-turbo".to_string())),
+                ("temperature".to_string(), FrontMatterType::FLOAT(0.2)),
+                ("seed".to_string(), FrontMatterType::FLOAT(-1000.0)),
+            ])))
+        );
+    }
+
+    #[test]
+    fn test_report_number_overflow() {
+        let source = r#"---
+variables:
+  "limit": 99999999999999999999
+model:
+  seed: -99999999999999999999
+---
+"#;
+
+        let result = parse_recovering(source);
+        let diagnostics: Vec<(DiagnosticCode, usize, usize, &str)> = result
+            .diagnostics
+            .iter()
+            .map(|it| (it.code, it.line, it.column, it.message.as_str()))
+            .collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                (DiagnosticCode::NumberOverflow, 3, 12, "number `99999999999999999999` is out of range"),
+                (DiagnosticCode::NumberOverflow, 5, 9, "number `-99999999999999999999` is out of range"),
+            ]
+        );
+
+        let result = parse("---\nwhen: $size > 1e999\n---\n");
+        assert_eq!(result.file, None);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].code, DiagnosticCode::NumberOverflow);
+        assert_eq!((result.diagnostics[0].line, result.diagnostics[0].column), (2, 15));
+    }
+
+    #[test]
+    fn test_parse_query_statement() {
+        let input = r#"---
+variables:
+  "controllers": {
+    from {
+      PsiClass clazz, PsiMethod method
+    }
+    where {
+      clazz.getName() == "Foo" && method.isPublic
+    }
+    select {
+      clazz.id, clazz.name
+      "code"
+    }
+  }
+---
+"#;
+
+        let (_, hole) = parse_hobbit_hole(input).unwrap();
+        let query = match hole.variables.get("controllers").unwrap() {
+            VariableTransform::Query(query) => query,
+            other => panic!("unexpected variable {:?}", other),
+        };
+
+        let member = |object: &str, method: &str, arguments| {
+            MethodCall::new(
+                FrontMatterType::IDENTIFIER(object.to_string()),
+                FrontMatterType::IDENTIFIER(method.to_string()),
+                arguments,
+            )
+        };
+        assert_eq!(
+            query,
+            &ShirePsiQueryStatement::new(
+                vec![
+                    VariableElement::new("PsiClass".to_string(), "clazz".to_string()),
+                    VariableElement::new("PsiMethod".to_string(), "method".to_string()),
+                ],
+                StatementType::LogicalExpression(LogicalExpression::new(
+                    StatementType::Comparison(Comparison::new(
+                        FrontMatterType::EXPRESSION(StatementType::MethodCall(member("clazz", "getName", Some(vec![])))),
+                        Operator::new(OperatorType::Equal),
+                        FrontMatterType::STRING("Foo".to_string()),
+                    )),
+                    OperatorType::And,
+                    StatementType::MethodCall(member("method", "isPublic", None)),
+                )),
+                vec![
+                    StatementType::MethodCall(member("clazz", "id", None)),
+                    StatementType::MethodCall(member("clazz", "name", None)),
+                    StatementType::Value(Value::new(FrontMatterType::STRING("code".to_string()))),
+                ],
+            )
+        );
+        assert_eq!(
+            query.to_string(),
+            "from {\n    PsiClass clazz, PsiMethod method\n}\nwhere {\n    clazz.getName() == \"Foo\" && method.isPublic\n}\nselect {\n    clazz.id, clazz.name, \"code\"\n}"
+        );
+        assert_eq!(query.to_pattern_action_funcs().len(), 3);
+
+        let object = |entries: &[(&str, RuntimeValue)]| {
+            RuntimeValue::Object(entries.iter().map(|(key, value)| (key.to_string(), value.clone())).collect())
+        };
+        let clazz = object(&[("name", RuntimeValue::from("Foo")), ("id", RuntimeValue::Int(1))]);
+        let method = object(&[("isPublic", RuntimeValue::Bool(true))]);
+        let env = Environment::new();
+        assert_eq!(query.matches(&env, &[clazz.clone(), method.clone()]), Ok(true));
+        assert_eq!(
+            query.select(&env, &[clazz.clone(), method]),
+            Ok(vec![RuntimeValue::Int(1), RuntimeValue::from("Foo"), RuntimeValue::from("code")])
+        );
+        assert_eq!(query.matches(&env, &[clazz]), Err("query binds 2 variables, but got 1 elements".to_string()));
+    }
+
+    #[test]
+    fn test_report_unknown_key_and_interaction() {
+        let source = r#"---
+name: "Summary"
+nmae: "Summary"
+interaction: AppendCurson
+---
+"#;
+
+        let result = parse(source);
+        let file = result.file.unwrap();
+        assert_eq!(file.hobbit.name, "Summary".to_string());
+        assert_eq!(file.hobbit.interaction, Some(InteractionType::RunPanel));
+
+        assert_eq!(result.diagnostics.len(), 2);
+        let unknown_key = &result.diagnostics[0];
+        assert_eq!(unknown_key.severity, Severity::Warning);
+        assert_eq!(unknown_key.code.code(), "SH001");
+        assert_eq!((unknown_key.line, unknown_key.column), (3, 1));
+        assert_eq!(
+            unknown_key.render(source),
+            "warning[SH001]: unknown key `nmae`\n --> 3:1\n  |\n3 | nmae: \"Summary\"\n  | ^^^^"
+        );
+
+        let interaction = &result.diagnostics[1];
+        assert_eq!(interaction.severity, Severity::Error);
+        assert_eq!(interaction.code, DiagnosticCode::UnknownInteraction);
+        assert_eq!((interaction.line, interaction.column), (4, 14));
+        assert_eq!(&source[interaction.span.start..interaction.span.end], "AppendCurson");
+    }
+
+    #[test]
+    fn test_report_invalid_value() {
+        let source = "---\nname: Summary\n---\n";
+
+        let result = parse(source);
+        assert_eq!(result.file, None);
+        assert!(result.has_errors());
+        assert_eq!(result.diagnostics.len(), 1);
+
+        let diagnostic = &result.diagnostics[0];
+        assert_eq!(diagnostic.code, DiagnosticCode::InvalidValue);
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 7));
+        assert_eq!(
+            diagnostic.message,
+            "invalid value of `name`, expected a quoted string like `\"Summary\"`".to_string()
+        );
+    }
+
+    #[test]
+    fn test_report_misused_functions() {
+        let source = r#"---
+variables:
+  "var1": /.*.java/ { grep("a|b") | head("ten") | parseCode("java") }
+  "var2": /.*.log/ {
+    case "$0" {
+      "error" { grep("ERROR") | head("5") }
+      default { sed("(","x") }
+    }
+  }
+afterStreaming: { capture("a.java") | saveFile("out.java") }
+---
+"#;
+
+        let result = parse(source);
+        assert!(result.file.is_some());
+
+        let diagnostics: Vec<(DiagnosticCode, usize, usize, &str)> = result
+            .diagnostics
+            .iter()
+            .map(|it| (it.code, it.line, it.column, it.message.as_str()))
+            .collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                (DiagnosticCode::InvalidArgument, 3, 37, "`head` expects a number of lines, found `ten`"),
+                (DiagnosticCode::InvalidArgument, 7, 17, "invalid regex `(` in `sed`"),
+                (DiagnosticCode::InvalidArgument, 10, 19, "`capture` expects 2 arguments, found 1"),
+            ]
         );
+
+        let hole = result.file.unwrap().hobbit;
+        match hole.variables.get("var1").unwrap() {
+            VariableTransform::PatternAction { command, .. } => assert_eq!(
+                command.to_pattern_action_funcs(),
+                vec![
+                    PatternActionFunc::Grep { patterns: vec![string("a|b")] },
+                    PatternActionFunc::ToolchainFunction { func_name: "head".to_string(), args: string_args(&["ten"]) },
+                    PatternActionFunc::ToolchainFunction {
+                        func_name: "parseCode".to_string(),
+                        args: string_args(&["java"]),
+                    },
+                ]
+            ),
+            other => panic!("unexpected variable {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_report_unclosed_front_matter() {
+        let result = parse("---\nname: \"Summary\"\n}\n");
+        assert_eq!(result.file, None);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].code, DiagnosticCode::SyntaxError);
+        assert_eq!((result.diagnostics[0].line, result.diagnostics[0].column), (3, 1));
+    }
+
+    #[test]
+    fn test_report_non_ascii_key() {
+        let result = parse("---\n“name”: x\n---\n");
+        assert_eq!(result.file, None);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].message, "expected a front matter key like `name:`");
+        assert_eq!(result.diagnostics[0].span, Span::new(4, 7));
+
+        let result = parse("  é\n");
+        assert_eq!(result.diagnostics[0].message, "expected `---` to start the front matter");
+        assert_eq!(result.diagnostics[0].span, Span::new(2, 4));
+    }
+
+    #[test]
+    fn test_report_missing_function_name() {
+        let result = parse("---\nvariables:\n  \"a\": /x/ { (\"a\") }\n---\n");
+        assert_eq!(result.file, None);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].message, "invalid value of variable `a`");
+        assert_eq!((result.diagnostics[0].line, result.diagnostics[0].column), (3, 8));
+
+        let result = parse("---\nafterStreaming: { (\"a\") }\n---\n");
+        assert_eq!(result.file, None);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].code, DiagnosticCode::InvalidValue);
+        assert_eq!((result.diagnostics[0].line, result.diagnostics[0].column), (2, 19));
+    }
+
+    #[test]
+    fn test_recover_from_malformed_variable() {
+        let source = r#"---
+name: Summary
+variables:
+  "var1": "demo"
+  "var2": /.*.java/ { grep("error.log" | sort }
+  "var3": 42
+description: "Generate Summary"
+---
+
+$var1
+"#;
+
+        assert_eq!(parse(source).file, None);
+
+        let result = parse_recovering(source);
+        let file = result.file.unwrap();
+        assert_eq!(file.hobbit.name, "".to_string());
+        assert_eq!(file.hobbit.description, Some("\"Generate Summary\"".to_string()));
+        assert_eq!(
+            file.hobbit.variables,
+            vec![
+                ("var1".to_string(), VariableTransform::String("demo".to_string())),
+                ("var3".to_string(), VariableTransform::Integer(42)),
+            ].into_iter().collect::<IndexMap<_, _>>()
+        );
+        let body: Vec<BodyNodeKind> = file.body.into_iter().map(|node| node.kind).collect();
+        assert_eq!(body, vec![BodyNodeKind::Variable("var1".to_string()), BodyNodeKind::Text("\n".to_string())]);
+
+        let positions: Vec<(DiagnosticCode, usize)> =
+            result.diagnostics.iter().map(|it| (it.code, it.line)).collect();
+        assert_eq!(positions, vec![(DiagnosticCode::InvalidValue, 2), (DiagnosticCode::InvalidValue, 5)]);
+        assert_eq!(result.diagnostics[1].message, "invalid value of variable `var2`".to_string());
+    }
+
+    #[test]
+    fn test_recover_from_stray_line_in_variables() {
+        let source = "---\nvariables:\n  \"a\": \"x\"\n  junk\n  \"b\": \"ok\"\nname: \"Summary\"\n---\n";
+        assert_eq!(parse(source).file, None);
+
+        let result = parse_recovering(source);
+        let file = result.file.unwrap();
+        assert_eq!(file.hobbit.name, "Summary".to_string());
+        assert_eq!(file.hobbit.variables.keys().collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].code, DiagnosticCode::SyntaxError);
+        assert_eq!((result.diagnostics[0].line, result.diagnostics[0].column), (4, 3));
+    }
+
+    #[test]
+    fn test_recover_without_front_matter_end() {
+        let result = parse_recovering("---\nname: \"Summary\"\nvariables:\n  \"var1\": \"demo\"\n");
+        let file = result.file.unwrap();
+        assert_eq!(file.hobbit.name, "Summary".to_string());
+        assert_eq!(file.hobbit.variables.len(), 1);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].message, "expected `---` to close the front matter".to_string());
     }
 }
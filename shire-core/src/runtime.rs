@@ -0,0 +1,467 @@
+use crate::ast::pattern_action_fun::PatternActionFunc;
+use crate::compare::equals;
+use crate::methods::{standard_methods, MethodRegistry};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// The value of evaluated statement, for example: `$fileName.endsWith(".kt")` evaluates to `Bool(true)`
+/// and `$selection.length()` to `Int(42)`.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    /// The date in ISO 8601, for example: `2024-05-01` or `2024-05-01T10:30:00`
+    Date(String),
+    List(Vec<Value>),
+    /// The object with keys in declaration order, for example: `{name: a, size: 1}`
+    Object(IndexMap<String, Value>),
+    /// The file handle from host, which is the path of file, for example: `src/main.rs`
+    File(String),
+    /// The code symbol handle from host, which is the qualified name, for example: `com.example.Foo#bar`
+    Symbol(String),
+    /// The pipeline to run, for example: the arm of case block `{ grep("class") | sort }`
+    Pipeline(Vec<PatternActionFunc>),
+}
+
+impl Value {
+    /// The name of type used in error message, for example: `expected bool, found string`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::String(_) => "string",
+            Value::Date(_) => "date",
+            Value::List(_) => "list",
+            Value::Object(_) => "object",
+            Value::File(_) => "file",
+            Value::Symbol(_) => "symbol",
+            Value::Pipeline(_) => "pipeline",
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The number as float, the int is widened to float
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Int(value) => Some(*value as f64),
+            Value::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The text of string, date, file path or symbol name
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) | Value::Date(value) | Value::File(value) | Value::Symbol(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&IndexMap<String, Value>> {
+        match self {
+            Value::Object(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The member for `$file.name`, which is the value of object key or the property of file and symbol handles:
+    /// `path`, `name` and `extension` of file, `name` and `qualifiedName` of symbol.
+    pub fn member(&self, name: &str) -> Option<Value> {
+        match (self, name) {
+            (Value::Object(entries), name) => entries.get(name).cloned(),
+            (Value::File(path), "path") => Some(Value::from(path.as_str())),
+            (Value::File(path), "name") => Some(Value::from(Path::new(path).file_name()?.to_str())),
+            (Value::File(path), "extension") => Some(Value::from(Path::new(path).extension().and_then(|it| it.to_str()))),
+            (Value::Symbol(name), "qualifiedName") => Some(Value::from(name.as_str())),
+            (Value::Symbol(name), "name") => name.rsplit(['.', '#']).next().map(Value::from),
+            _ => None,
+        }
+    }
+
+    /// The bool value of condition, it's an error if the value is not bool, for example: `$a && "x"`
+    pub fn expect_bool(&self, what: &str) -> Result<bool, String> {
+        self.as_bool().ok_or_else(|| format!("{} expected bool, found {}", what, self.type_name()))
+    }
+
+    /// The type and value used in error message, for example: string `abc`, or only the type for list and object
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Value::Null | Value::List(_) | Value::Object(_) | Value::Pipeline(_) => self.type_name().to_string(),
+            value => format!("{} `{}`", value.type_name(), value),
+        }
+    }
+
+    /// The number of value for arithmetic, the text is parsed as int or float, for example: `"120"` from host
+    pub fn to_number(&self) -> Option<Value> {
+        match self {
+            Value::Int(_) | Value::Float(_) => Some(self.clone()),
+            Value::String(text) => {
+                let text = text.trim();
+                match text.parse::<i64>() {
+                    Ok(value) => Some(Value::Int(value)),
+                    Err(_) => text.parse::<f64>().ok().filter(|it| it.is_finite()).map(Value::Float),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The result of `+`, which concatenates if either operand is string or both are lists, for example:
+    /// `$name + ".kt"`, and adds the numbers otherwise.
+    pub fn add(&self, other: &Value) -> Result<Value, String> {
+        match (self, other) {
+            (Value::List(left), Value::List(right)) => Ok(Value::List(left.iter().chain(right).cloned().collect())),
+            (Value::Null, _) | (_, Value::Null) => self.arithmetic(other, "+", i64::checked_add, |a, b| a + b),
+            (Value::String(_), _) | (_, Value::String(_)) => Ok(Value::String(format!("{}{}", self, other))),
+            _ => self.arithmetic(other, "+", i64::checked_add, |a, b| a + b),
+        }
+    }
+
+    pub fn subtract(&self, other: &Value) -> Result<Value, String> {
+        self.arithmetic(other, "-", i64::checked_sub, |a, b| a - b)
+    }
+
+    pub fn multiply(&self, other: &Value) -> Result<Value, String> {
+        self.arithmetic(other, "*", i64::checked_mul, |a, b| a * b)
+    }
+
+    /// The quotient, which is truncated if both are int like `7 / 2 == 3`
+    pub fn divide(&self, other: &Value) -> Result<Value, String> {
+        self.check_divisor(other)?;
+        self.arithmetic(other, "/", i64::checked_div, |a, b| a / b)
+    }
+
+    pub fn remainder(&self, other: &Value) -> Result<Value, String> {
+        self.check_divisor(other)?;
+        self.arithmetic(other, "%", i64::checked_rem, |a, b| a % b)
+    }
+
+    fn check_divisor(&self, divisor: &Value) -> Result<(), String> {
+        match divisor.to_number().and_then(|it| it.as_float()) {
+            Some(0.0) => Err("division by zero".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Apply the operator to numbers, the result is int if both are int and float otherwise
+    fn arithmetic(
+        &self,
+        other: &Value,
+        operator: &str,
+        int: fn(i64, i64) -> Option<i64>,
+        float: fn(f64, f64) -> f64,
+    ) -> Result<Value, String> {
+        match (self.to_number(), other.to_number()) {
+            (Some(Value::Int(left)), Some(Value::Int(right))) => int(left, right)
+                .map(Value::Int)
+                .ok_or_else(|| format!("integer overflow in `{} {} {}`", left, operator, right)),
+            (Some(left), Some(right)) => {
+                // both are numbers, so they can be converted to float
+                Ok(Value::Float(float(left.as_float().unwrap_or_default(), right.as_float().unwrap_or_default())))
+            }
+            _ => Err(format!("cannot apply `{}` to {} and {}", operator, self.describe(), other.describe())),
+        }
+    }
+
+    /// Whether `element` is in the value for `in`, which is the element of list, the key of object or the
+    /// substring of text, for example: `$lang in ["kotlin", "java"]`
+    pub fn contains(&self, element: &Value) -> Result<bool, String> {
+        match (self, element.as_str()) {
+            (Value::List(values), _) => Ok(values.iter().any(|it| equals(it, element))),
+            (Value::Object(entries), Some(key)) => Ok(entries.contains_key(key)),
+            (Value::String(text), Some(part)) => Ok(text.contains(part)),
+            _ => Err(format!("cannot check if {} is in {}", element.describe(), self.describe())),
+        }
+    }
+}
+
+/// The numbers are equal if they have the same value, so `1 == 1.0`, other values are equal only to
+/// the same type.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => self.as_float() == other.as_float(),
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Date(a), Value::Date(b)) => a == b,
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::Object(a), Value::Object(b)) => a == b,
+            (Value::File(a), Value::File(b)) => a == b,
+            (Value::Symbol(a), Value::Symbol(b)) => a == b,
+            (Value::Pipeline(a), Value::Pipeline(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// The text used in prompt, the string is not quoted, for example: `[a, b]` and `{size: 1}`
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Int(value) => write!(f, "{}", value),
+            // debug format keeps the fraction of `1.0`, so it's not mistaken for int
+            Value::Float(value) => write!(f, "{:?}", value),
+            Value::String(value) | Value::Date(value) | Value::File(value) | Value::Symbol(value) => {
+                write!(f, "{}", value)
+            }
+            Value::List(values) => {
+                write!(f, "[{}]", values.iter().map(|it| it.to_string()).collect::<Vec<_>>().join(", "))
+            }
+            Value::Object(entries) => {
+                let entries = entries.iter().map(|(key, value)| format!("{}: {}", key, value)).collect::<Vec<_>>();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+            Value::Pipeline(funcs) => {
+                write!(f, "{}", funcs.iter().map(|it| it.to_string()).collect::<Vec<_>>().join(" | "))
+            }
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+/// The length and index are int, they never exceed `i64::MAX` in practice
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Value::Int(value as i64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Self {
+        Value::List(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Value::Null)
+    }
+}
+
+/// The host which supplies the builtin variables, for example: `$selection` or `$fileName` in IDE,
+/// they are looked up after the variables defined in scopes.
+pub trait Host {
+    fn variable(&self, name: &str) -> Option<Value>;
+}
+
+impl Host for HashMap<String, Value> {
+    fn variable(&self, name: &str) -> Option<Value> {
+        self.get(name).cloned()
+    }
+}
+
+/// The scope of variables to evaluate statements, the nested scope shadows the variables of its parent,
+/// for example: the captured groups of case arm or the elements bound by query.
+///
+/// ```rust
+/// use shire_core::runtime::{Environment, Value};
+///
+/// let mut env = Environment::new();
+/// env.define("fileName", "Main.kt");
+///
+/// let mut arm = env.child();
+/// arm.define("0", "Main");
+/// assert_eq!(arm.get("fileName"), Some(Value::from("Main.kt")));
+/// assert_eq!(env.get("0"), None);
+/// ```
+#[derive(Default)]
+pub struct Environment<'a> {
+    variables: HashMap<String, Value>,
+    parent: Option<&'a Environment<'a>>,
+    host: Option<&'a dyn Host>,
+    methods: Option<&'a MethodRegistry>,
+}
+
+impl<'a> Environment<'a> {
+    pub fn new() -> Self {
+        Environment::default()
+    }
+
+    /// The root scope whose builtin variables are supplied by `host`
+    pub fn with_host(host: &'a dyn Host) -> Self {
+        Environment { variables: HashMap::new(), parent: None, host: Some(host), methods: None }
+    }
+
+    /// Call the methods of `methods` instead of the standard ones, for example: the registry extended by host
+    pub fn with_methods(mut self, methods: &'a MethodRegistry) -> Self {
+        self.methods = Some(methods);
+        self
+    }
+
+    /// The nested scope, the variables defined in it are dropped with it
+    pub fn child(&'a self) -> Environment<'a> {
+        Environment { variables: HashMap::new(), parent: Some(self), host: None, methods: None }
+    }
+
+    pub fn define(&mut self, name: impl Into<String>, value: impl Into<Value>) {
+        self.variables.insert(name.into(), value.into());
+    }
+
+    /// Look up the variable from the innermost scope to the root, then the builtin variables of host
+    pub fn get(&self, name: &str) -> Option<Value> {
+        if let Some(value) = self.variables.get(name) {
+            return Some(value.clone());
+        }
+
+        match self.parent {
+            Some(parent) => parent.get(name),
+            None => self.host.and_then(|host| host.variable(name)),
+        }
+    }
+
+    /// The methods which can be called in expression, the nested scope uses the methods of its root
+    pub fn methods(&self) -> &MethodRegistry {
+        match (self.methods, self.parent) {
+            (Some(methods), _) => methods,
+            (None, Some(parent)) => parent.methods(),
+            (None, None) => standard_methods(),
+        }
+    }
+}
+
+/// The variables of text, for example: the variables resolved before the `when` condition
+impl<'a> From<HashMap<String, String>> for Environment<'a> {
+    fn from(variables: HashMap<String, String>) -> Self {
+        let variables = variables.into_iter().map(|(name, value)| (name, Value::String(value))).collect();
+        Environment { variables, parent: None, host: None, methods: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_equality() {
+        assert_eq!(Value::Int(1), Value::Float(1.0));
+        assert_eq!(Value::from(vec!["a", "b"]), Value::List(vec![Value::from("a"), Value::from("b")]));
+        assert_ne!(Value::from("1"), Value::Int(1));
+        assert_ne!(Value::File("a.rs".to_string()), Value::from("a.rs"));
+        assert_eq!(Value::from(None::<i64>), Value::Null);
+    }
+
+    #[test]
+    fn test_value_display() {
+        let object = IndexMap::from([("size".to_string(), Value::Int(1)), ("name".to_string(), Value::from("a"))]);
+        assert_eq!(Value::Object(object).to_string(), "{size: 1, name: a}");
+        assert_eq!(Value::from(vec![Value::Float(1.0), Value::Null, Value::Bool(true)]).to_string(), "[1.0, null, true]");
+        assert_eq!(Value::Symbol("com.example.Foo#bar".to_string()).to_string(), "com.example.Foo#bar");
+    }
+
+    #[test]
+    fn test_value_conversions() {
+        assert_eq!(Value::Int(2).as_float(), Some(2.0));
+        assert_eq!(Value::Float(2.0).as_int(), None);
+        assert_eq!(Value::File("a.rs".to_string()).as_str(), Some("a.rs"));
+        assert_eq!(Value::from("x").expect_bool("left operand"), Err("left operand expected bool, found string".to_string()));
+    }
+
+    #[test]
+    fn test_value_arithmetic() {
+        assert_eq!(Value::Int(7).divide(&Value::Int(2)), Ok(Value::Int(3)));
+        assert_eq!(Value::from("1.5").multiply(&Value::Int(2)), Ok(Value::Float(3.0)));
+        assert_eq!(Value::from(vec![1i64]).add(&Value::from(vec![2i64])), Ok(Value::from(vec![1i64, 2])));
+        assert_eq!(Value::File("a.rs".to_string()).add(&Value::from(".bak")), Ok(Value::from("a.rs.bak")));
+        assert_eq!(Value::Int(i64::MAX).add(&Value::Int(1)), Err(format!("integer overflow in `{} + 1`", i64::MAX)));
+        assert_eq!(Value::Null.add(&Value::from("x")), Err("cannot apply `+` to null and string `x`".to_string()));
+        assert_eq!(Value::Int(1).remainder(&Value::Float(0.0)), Err("division by zero".to_string()));
+    }
+
+    #[test]
+    fn test_value_contains() {
+        assert_eq!(Value::from(vec![Value::Int(1)]).contains(&Value::Float(1.0)), Ok(true));
+        assert_eq!(Value::Object(IndexMap::new()).contains(&Value::from("a")), Ok(false));
+        assert_eq!(Value::Int(1).contains(&Value::Int(1)), Err("cannot check if int `1` is in int `1`".to_string()));
+    }
+
+    #[test]
+    fn test_value_member() {
+        let file = Value::File("src/main.rs".to_string());
+        assert_eq!(file.member("name"), Some(Value::from("main.rs")));
+        assert_eq!(file.member("extension"), Some(Value::from("rs")));
+        assert_eq!(Value::Symbol("com.example.Foo#bar".to_string()).member("name"), Some(Value::from("bar")));
+        assert_eq!(Value::from("main.rs").member("name"), None);
+    }
+
+    #[test]
+    fn test_environment_scopes() {
+        let host = HashMap::from([("selection".to_string(), Value::from("fn main() {}"))]);
+        let mut env = Environment::with_host(&host);
+        env.define("selection", "shadowed");
+        env.define("files", vec![Value::File("a.rs".to_string())]);
+
+        let mut scope = env.child();
+        scope.define("files", Value::Null);
+        assert_eq!(scope.get("files"), Some(Value::Null));
+        assert_eq!(scope.get("selection"), Some(Value::from("shadowed")));
+        assert_eq!(env.get("files"), Some(Value::from(vec![Value::File("a.rs".to_string())])));
+
+        let env = Environment::with_host(&host);
+        assert_eq!(env.child().get("selection"), Some(Value::from("fn main() {}")));
+        assert_eq!(env.get("unknown"), None);
+    }
+}
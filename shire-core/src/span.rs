@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+/// Byte range in the source text, `end` is exclusive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// The span of `slice`, which must be a sub-slice of `source`.
+    pub(crate) fn of(source: &str, slice: &str) -> Span {
+        let base = source.as_ptr() as usize;
+        let start = (slice.as_ptr() as usize).saturating_sub(base).min(source.len());
+        Span::new(start, (start + slice.len()).min(source.len()))
+    }
+
+    /// The 1-based line and column (counted in chars) where the span starts.
+    pub fn line_column(&self, source: &str) -> (usize, usize) {
+        let start = self.start.min(source.len());
+        let before = &source[..start];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|index| index + 1).unwrap_or(0);
+        let column = source[line_start..start].chars().count() + 1;
+        (line, column)
+    }
+}
+
+/// The span attached to a node of AST, which is ignored when comparing the nodes, so that the same
+/// expression parsed from different places or layouts is still equal.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct NodeSpan(pub Span);
+
+impl PartialEq for NodeSpan {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for NodeSpan {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_column() {
+        let source = "---\nname: \"测试\"\nfoo: 1\n---";
+        let at = |text: &str| {
+            let start = source.find(text).unwrap();
+            Span::new(start, start + text.len())
+        };
+
+        assert_eq!(Span::new(0, 3).line_column(source), (1, 1));
+        assert_eq!(at("测试").line_column(source), (2, 8));
+        assert_eq!(at("\"\n").line_column(source), (2, 10));
+        assert_eq!(at("foo").line_column(source), (3, 1));
+        assert_eq!(Span::new(source.len(), source.len()).line_column(source), (4, 4));
+    }
+}